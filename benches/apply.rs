@@ -0,0 +1,101 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use termdraw::{Colour, Draw, Format, Region};
+
+const WIDTH: usize = 200;
+const HEIGHT: usize = 60;
+
+/*
+ * A large screen for measuring frames in which little has changed, where the
+ * cost is dominated by finding what has.
+ */
+const BIG_WIDTH: usize = 300;
+const BIG_HEIGHT: usize = 100;
+
+/*
+ * Fill the region with text in a variety of colours, so that every frame
+ * involves plenty of format changes.  The frame number is mixed in so that
+ * each frame differs from the last.
+ */
+fn paint(r: &mut Region, frame: usize) {
+    for y in 0..r.height() {
+        for x in 0..r.width() {
+            let n = (x + y + frame) % 256;
+            let f = Format {
+                fg: Colour::RGB(n as u8, 255 - n as u8, 128),
+                bg: Colour::C256(n as u8),
+                bold: n % 3 == 1,
+                ..Default::default()
+            };
+            r.chrf(x, y, (b'a' + (n % 26) as u8) as char, &f);
+        }
+    }
+}
+
+fn full_frames(c: &mut Criterion) {
+    let mut frames = [Region::new(WIDTH, HEIGHT), Region::new(WIDTH, HEIGHT)];
+    paint(&mut frames[0], 0);
+    paint(&mut frames[1], 1);
+
+    c.bench_function("full frame", |b| {
+        let mut draw = Draw::new(WIDTH, HEIGHT);
+        let mut out = std::io::sink();
+        let mut n = 0;
+        b.iter(|| {
+            n += 1;
+            draw.apply_to(&frames[n % 2], &mut out).unwrap();
+        })
+    });
+}
+
+fn small_changes(c: &mut Criterion) {
+    let mut g = c.benchmark_group("small changes");
+
+    /*
+     * Each benchmark starts from a painted screen that has already been drawn
+     * once, and then makes its changes to the same region on every frame.
+     */
+    let setup = || {
+        let mut r = Region::new(BIG_WIDTH, BIG_HEIGHT);
+        paint(&mut r, 0);
+        let mut draw = Draw::new(BIG_WIDTH, BIG_HEIGHT);
+        draw.apply_to(&r, &mut std::io::sink()).unwrap();
+        (r, draw)
+    };
+
+    g.bench_function("unchanged", |b| {
+        let (r, mut draw) = setup();
+        let mut out = std::io::sink();
+        b.iter(|| draw.apply_to(&r, &mut out).unwrap())
+    });
+
+    g.bench_function("status line", |b| {
+        let (mut r, mut draw) = setup();
+        let mut out = std::io::sink();
+        let mut n = 0;
+        b.iter(|| {
+            n += 1;
+            r.str(0, BIG_HEIGHT - 1, &format!("frame {:8}", n));
+            draw.apply_to(&r, &mut out).unwrap();
+        })
+    });
+
+    g.bench_function("scattered cells", |b| {
+        let (mut r, mut draw) = setup();
+        let mut out = std::io::sink();
+        let mut n = 0;
+        b.iter(|| {
+            n += 1;
+            for i in 0..10 {
+                let (x, y) =
+                    ((n * 7 + i * 31) % BIG_WIDTH, (n + i * 13) % BIG_HEIGHT);
+                r.chr(x, y, (b'a' + (n % 26) as u8) as char);
+            }
+            draw.apply_to(&r, &mut out).unwrap();
+        })
+    });
+
+    g.finish();
+}
+
+criterion_group!(benches, full_frames, small_changes);
+criterion_main!(benches);
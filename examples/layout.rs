@@ -2,12 +2,11 @@ use chrono::prelude::*;
 use chrono_tz::Tz;
 use rand::prelude::*;
 use std::collections::VecDeque;
-use std::io::Read;
-use std::mem::MaybeUninit;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
-use termdraw::Colour;
+use termdraw::{Align, Colour, Rect, TextLayout};
 use termios::{TCIOFLUSH, TCSADRAIN, TCSANOW};
 
 const YELLOW: Colour = Colour::RGB(0xF5, 0xCF, 0x75);
@@ -17,38 +16,20 @@ const GREEN_LIGHT: Colour = Colour::RGB(0x48, 0xD5, 0x97);
 const GREEN_DARK: Colour = Colour::RGB(0x11, 0x27, 0x25);
 const GREEN_DARKEST: Colour = Colour::RGB(0x0B, 0x14, 0x18);
 
-struct WinSize {
-    height: usize,
-    width: usize,
-}
-
 fn emit(io: &mut dyn std::io::Write, data: &str) -> std::io::Result<()> {
     io.write_all(data.as_bytes())?;
     io.flush()?;
     Ok(())
 }
 
-fn getwinsz(fd: RawFd) -> std::io::Result<WinSize> {
-    let mut winsize: MaybeUninit<libc::winsize> = MaybeUninit::uninit();
-    let r = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, winsize.as_mut_ptr()) };
-    if r != 0 {
-        Err(std::io::Error::last_os_error())
-    } else {
-        let winsize = unsafe { winsize.assume_init() };
-        Ok(WinSize {
-            height: winsize.ws_row as usize,
-            width: winsize.ws_col as usize,
-        })
-    }
-}
-
 pub fn main() {
     let mut rng = rand::thread_rng();
     let mut stdout = std::io::stdout();
-    let sz = getwinsz(stdout.as_raw_fd()).unwrap();
+    let mut winch = termdraw::ResizeWatcher::new(stdout.as_raw_fd()).unwrap();
+    let (width, height) = winch.size().unwrap();
 
-    let mut draw = termdraw::Draw::new(sz.width, sz.height);
-    draw.set_line_glitch(false);
+    let caps = termdraw::Capabilities::from_env();
+    let mut draw = termdraw::Draw::with_capabilities(width, height, &caps);
     {
         let (r, g, b) = GREEN_DARK.as_rgb();
         draw.preamble(&format!("\x1b[48;2;{};{};{}m\x0c", r, g, b));
@@ -97,7 +78,7 @@ pub fn main() {
      * Put the terminal in raw mode.
      */
     let orig_termios = termios::Termios::from_fd(stdout.as_raw_fd()).unwrap();
-    let mut termios = orig_termios.clone();
+    let mut termios = orig_termios;
     termios::cfmakeraw(&mut termios);
     termios::tcsetattr(stdout.as_raw_fd(), TCSANOW, &termios).unwrap();
     termios::tcflush(stdout.as_raw_fd(), TCIOFLUSH).unwrap();
@@ -138,22 +119,37 @@ pub fn main() {
             ring.pop_front();
         }
 
+        if let Some(Ok((width, height))) = winch.changed() {
+            /*
+             * The terminal has been resized.  Start again with a blank screen
+             * of the new size.
+             */
+            draw.resize(width, height);
+            r.resize(width, height);
+        }
+
         r.clear();
 
         let hh = 3;
         let hf = 2;
 
-        let mut f = termdraw::Format::default();
-        f.bg = GREEN_DARK;
-        f.fg = GREEN_LIGHT;
+        let f = termdraw::Format {
+            bg: GREEN_DARK,
+            fg: GREEN_LIGHT,
+            ..Default::default()
+        };
 
-        let mut yf = termdraw::Format::default();
-        yf.bg = GREEN_DARK;
-        yf.fg = YELLOW;
+        let yf = termdraw::Format {
+            bg: GREEN_DARK,
+            fg: YELLOW,
+            ..Default::default()
+        };
 
-        let mut ff = termdraw::Format::default();
-        ff.bg = GREEN_DARKEST;
-        ff.fg = OFF_WHITE;
+        let ff = termdraw::Format {
+            bg: GREEN_DARKEST,
+            fg: OFF_WHITE,
+            ..Default::default()
+        };
 
         let oxide = include_str!("oxide.txt")
             .lines()
@@ -161,27 +157,22 @@ pub fn main() {
             .collect::<Vec<_>>();
         let oxidew = oxide.iter().map(|l| l.len()).max().unwrap_or(0);
 
-        for y in 0..hh {
-            for x in 0..r.width() {
-                r.chrf(x, y, ' ', &f);
-            }
-        }
-        for y in hh..(r.height() - hf - 1) {
-            for x in 0..r.width() {
-                r.chrf(x, y, ' ', &ff);
-            }
-        }
-        for y in (r.height() - hf - 1)..r.height() {
-            for x in 0..r.width() {
-                r.chrf(x, y, ' ', &f);
-            }
-        }
+        let (w, h) = (r.width(), r.height());
+        r.paint_rect(Rect::new(0, 0, w, hh), &f);
+        r.paint_rect(Rect::new(0, hh, w, h - hf - 1 - hh), &ff);
+        r.paint_rect(Rect::new(0, h - hf - 1, w, hf + 1), &f);
 
         let msgl = "OXIDE COMPUTER COMPANY";
         let msgr = "PROGRAMMING STATION";
 
         r.strf(3, 1, msgl, &f);
-        r.strf(r.width() - 3 - msgr.len(), 1, msgr, &f);
+        let right = TextLayout { align: Align::Right, ..Default::default() };
+        r.text_layout(
+            Rect::new(3, 1, w.saturating_sub(6), 1),
+            msgr,
+            &f,
+            &right,
+        );
 
         let ftrl = format!("STATION: {}", nodename.to_ascii_uppercase());
         r.strf(3, r.height() - 2, &ftrl, &yf);
@@ -189,30 +180,31 @@ pub fn main() {
         let now = Utc::now().with_timezone(&tz);
         let ftrr =
             now.format("%Y-%b-%d %H:%M:%S").to_string().to_ascii_uppercase();
-        r.strf(r.width() - 3 - ftrr.len(), r.height() - 2, &ftrr, &yf);
+        r.text_layout(
+            Rect::new(3, h - 2, w.saturating_sub(6), 1),
+            &ftrr,
+            &yf,
+            &right,
+        );
 
         let offs = r.width() - oxidew - 1;
         let hoff = r.height() - hf - oxide.len() - 2;
 
-        for y in 0..oxide.len() {
-            for x in 0..oxidew {
-                if x >= oxide[y].len() {
-                    continue;
-                }
-
-                if oxide[y][x] == '#' {
+        for (y, line) in oxide.iter().enumerate() {
+            for (x, c) in line.iter().enumerate() {
+                if *c == '#' {
                     r.chrf(offs + x, hoff + y, ' ', &f);
                 }
             }
         }
 
-        let mut ftxt = termdraw::Format::default();
-        ftxt.bg = Colour::UseExisting;
-        ftxt.fg = OFF_WHITE;
-        let mut fwarn = ftxt.clone();
-        fwarn.fg = YELLOW;
-        let mut fcrit = ftxt.clone();
-        fcrit.fg = RED;
+        let ftxt = termdraw::Format {
+            bg: Colour::UseExisting,
+            fg: OFF_WHITE,
+            ..Default::default()
+        };
+        let fwarn = termdraw::Format { fg: YELLOW, ..ftxt };
+        let fcrit = termdraw::Format { fg: RED, ..ftxt };
 
         let offs = 10;
         let hoff = 6;
@@ -241,8 +233,8 @@ pub fn main() {
             );
         }
 
-        let out = draw.apply(&r);
-        if !emit(&mut stdout, &out).is_ok() {
+        if draw.apply_to(&r, &mut stdout).and_then(|_| stdout.flush()).is_err()
+        {
             break;
         }
 
@@ -276,6 +268,13 @@ pub fn main() {
                      */
                     break 'outer;
                 }
+                if c == 0x0c {
+                    /*
+                     * ^L means redraw the whole screen right away.
+                     */
+                    draw.invalidate();
+                    break;
+                }
                 if c == b'q' || c == b'Q' {
                     quit = true;
                     break;
@@ -1,8 +1,7 @@
 use rand::prelude::*;
 use std::collections::HashSet;
-use std::io::Read;
-use std::mem::MaybeUninit;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 use termios::{TCIOFLUSH, TCSADRAIN, TCSANOW};
@@ -18,11 +17,6 @@ struct Orb {
     ramp: &'static [u8],
 }
 
-struct WinSize {
-    height: usize,
-    width: usize,
-}
-
 const MISSION: &[&str] = &[
     "kick butt",
     "have fun",
@@ -67,26 +61,14 @@ fn emit(io: &mut dyn std::io::Write, data: &str) -> std::io::Result<()> {
     Ok(())
 }
 
-fn getwinsz(fd: RawFd) -> std::io::Result<WinSize> {
-    let mut winsize: MaybeUninit<libc::winsize> = MaybeUninit::uninit();
-    let r = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, winsize.as_mut_ptr()) };
-    if r != 0 {
-        Err(std::io::Error::last_os_error())
-    } else {
-        let winsize = unsafe { winsize.assume_init() };
-        Ok(WinSize {
-            height: winsize.ws_row as usize,
-            width: winsize.ws_col as usize,
-        })
-    }
-}
-
 pub fn main() {
     let mut rng = rand::thread_rng();
     let mut stdout = std::io::stdout();
-    let sz = getwinsz(stdout.as_raw_fd()).unwrap();
+    let mut winch = termdraw::ResizeWatcher::new(stdout.as_raw_fd()).unwrap();
+    let (width, height) = winch.size().unwrap();
 
-    let mut draw = termdraw::Draw::new(sz.width, sz.height);
+    let caps = termdraw::Capabilities::from_env();
+    let mut draw = termdraw::Draw::with_capabilities(width, height, &caps);
     let mut r = termdraw::Region::new(draw.width(), draw.height());
 
     let msg = "press q to quit...";
@@ -131,7 +113,7 @@ pub fn main() {
      * Put the terminal in raw mode.
      */
     let orig_termios = termios::Termios::from_fd(stdout.as_raw_fd()).unwrap();
-    let mut termios = orig_termios.clone();
+    let mut termios = orig_termios;
     termios::cfmakeraw(&mut termios);
     termios::tcsetattr(stdout.as_raw_fd(), TCSANOW, &termios).unwrap();
     termios::tcflush(stdout.as_raw_fd(), TCIOFLUSH).unwrap();
@@ -182,11 +164,19 @@ pub fn main() {
             break;
         }
 
+        if let Some(Ok((width, height))) = winch.changed() {
+            /*
+             * The terminal has been resized.  Start again with a blank screen
+             * of the new size.
+             */
+            draw.resize(width, height);
+            r.resize(width, height);
+        }
+
         r.clear();
 
         for orb in orbs.iter_mut() {
-            let mut f = termdraw::Format::default();
-            f.fg = termdraw::Colour::C256(if orb.frame < orb.ramp.len() {
+            let fg = termdraw::Colour::C256(if orb.frame < orb.ramp.len() {
                 orb.ramp[orb.frame]
             } else if orb.frame < orb.ramp.len() * 2 {
                 let idx = orb.ramp.len() - 1 - (orb.frame - orb.ramp.len());
@@ -195,6 +185,7 @@ pub fn main() {
                 orb.active = false;
                 continue;
             });
+            let f = termdraw::Format { fg, ..Default::default() };
             orb.frame += orb.rate;
 
             r.strf(orb.x, orb.y, &orb.word, &f);
@@ -208,8 +199,8 @@ pub fn main() {
             inuse.remove(&rem.word);
         }
 
-        let out = draw.apply(&r);
-        if !emit(&mut stdout, &out).is_ok() {
+        if draw.apply_to(&r, &mut stdout).and_then(|_| stdout.flush()).is_err()
+        {
             break;
         }
 
@@ -243,6 +234,13 @@ pub fn main() {
                      */
                     break 'outer;
                 }
+                if c == 0x0c {
+                    /*
+                     * ^L means redraw the whole screen right away.
+                     */
+                    draw.invalidate();
+                    break;
+                }
                 if c == b'q' || c == b'Q' {
                     /*
                      * Every orb should decay at a faster rate once the user has
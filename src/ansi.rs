@@ -0,0 +1,568 @@
+use super::layout::TextLayout;
+use super::region::*;
+use super::text::{Line, Span, Text};
+
+/*
+ * Control sequences longer than this are assumed to be garbage, and are
+ * discarded without being interpreted.
+ */
+const MAX_PARAMS: usize = 256;
+
+const TAB_STOP: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    /**
+     * An OSC, DCS, SOS, PM, or APC string, which continues until BEL or ST.
+     */
+    String,
+    StringEscape,
+}
+
+/**
+ * Converts output containing ANSI escape sequences (e.g., from a compiler, or
+ * "ls --color") into rich text.  SGR sequences are applied to the format of
+ * the text that follows; all other control sequences and control characters,
+ * save for newlines and tabs, are discarded.  Input may be provided in pieces
+ * of any size as it arrives.
+ */
+pub struct AnsiParser {
+    state: State,
+    format: Format,
+    params: Vec<u8>,
+    private: bool,
+    utf8: Vec<u8>,
+    text: Text,
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        AnsiParser::new()
+    }
+}
+
+impl AnsiParser {
+    pub fn new() -> AnsiParser {
+        AnsiParser {
+            state: State::Ground,
+            format: Format::default(),
+            params: Vec::new(),
+            private: false,
+            utf8: Vec::new(),
+            text: Text::from(Line::new()),
+        }
+    }
+
+    /**
+     * The format that will be applied to the next text to arrive.
+     */
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /**
+     * The text parsed so far.
+     */
+    pub fn text(&self) -> &Text {
+        &self.text
+    }
+
+    /**
+     * Take the text parsed so far, leaving the parser ready to continue with
+     * an empty line, in the current format.
+     */
+    pub fn take(&mut self) -> Text {
+        std::mem::replace(&mut self.text, Text::from(Line::new()))
+    }
+
+    pub fn finish(mut self) -> Text {
+        if !self.utf8.is_empty() {
+            self.utf8.clear();
+            self.push_char('\u{FFFD}');
+        }
+        self.text
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) {
+        for &b in buf {
+            self.byte(b);
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        match self.state {
+            State::Ground => self.ground(b),
+            State::Escape => {
+                self.state = match b {
+                    b'[' => {
+                        self.params.clear();
+                        self.private = false;
+                        State::Csi
+                    }
+                    b']' | b'P' | b'X' | b'^' | b'_' => State::String,
+                    0x20..=0x2F => State::EscapeIntermediate,
+                    0x1B => State::Escape,
+                    _ => State::Ground,
+                };
+            }
+            State::EscapeIntermediate => {
+                self.state = match b {
+                    0x20..=0x2F => State::EscapeIntermediate,
+                    0x1B => State::Escape,
+                    _ => State::Ground,
+                };
+            }
+            State::Csi => match b {
+                0x30..=0x3F => {
+                    if self.params.is_empty() && matches!(b, b'<'..=b'?') {
+                        self.private = true;
+                    }
+                    if self.params.len() < MAX_PARAMS {
+                        self.params.push(b);
+                    } else {
+                        self.private = true;
+                    }
+                }
+                0x20..=0x2F => {
+                    /*
+                     * No sequence with intermediate bytes is of interest.
+                     */
+                    self.private = true;
+                }
+                0x40..=0x7E => {
+                    if b == b'm' && !self.private {
+                        self.sgr();
+                    }
+                    self.state = State::Ground;
+                }
+                0x1B => self.state = State::Escape,
+                0x18 | 0x1A => self.state = State::Ground,
+                _ => (),
+            },
+            State::String => match b {
+                0x07 | 0x18 | 0x1A => self.state = State::Ground,
+                0x1B => self.state = State::StringEscape,
+                _ => (),
+            },
+            State::StringEscape => {
+                /*
+                 * ESC \ is the string terminator.  Anything else aborts the
+                 * string and begins a new escape sequence.
+                 */
+                self.state = State::Ground;
+                if b != b'\\' {
+                    self.state = State::Escape;
+                    self.byte(b);
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, b: u8) {
+        if !self.utf8.is_empty() {
+            if b & 0xC0 == 0x80 {
+                self.utf8.push(b);
+                if self.utf8.len() == utf8_len(self.utf8[0]) {
+                    let ch = std::str::from_utf8(&self.utf8)
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or('\u{FFFD}');
+                    self.utf8.clear();
+                    self.push_char(ch);
+                }
+                return;
+            }
+
+            /*
+             * The sequence was cut short.
+             */
+            self.utf8.clear();
+            self.push_char('\u{FFFD}');
+        }
+
+        match b {
+            0x1B => self.state = State::Escape,
+            b'\n' => self.text.push(Line::new()),
+            b'\t' => {
+                let w = self.line().width();
+                let n = TAB_STOP - w % TAB_STOP;
+                (0..n).for_each(|_| self.push_char(' '));
+            }
+            0x20..=0x7E => self.push_char(b as char),
+            0xC2..=0xF4 => self.utf8.push(b),
+            0x80..=0xFF => self.push_char('\u{FFFD}'),
+            _ => {
+                /*
+                 * Other control characters are discarded.
+                 */
+            }
+        }
+    }
+
+    fn line(&mut self) -> &mut Line {
+        self.text.lines.last_mut().unwrap()
+    }
+
+    fn push_char(&mut self, ch: char) {
+        let f = self.format;
+        let line = self.line();
+        match line.spans.last_mut() {
+            Some(s) if s.format == f => s.text.push(ch),
+            _ => line.push(Span { text: ch.to_string(), format: f }),
+        }
+    }
+
+    /**
+     * Apply a Select Graphic Rendition sequence to the current format.  Each
+     * parameter may have sub-parameters separated by colons, as in "4:3" or
+     * "38:2::255:0:0".
+     */
+    fn sgr(&mut self) {
+        let params = std::str::from_utf8(&self.params).unwrap_or("");
+        let groups: Vec<Vec<Option<u32>>> = params
+            .split(';')
+            .map(|g| g.split(':').map(|p| p.parse().ok()).collect())
+            .collect();
+
+        let f = &mut self.format;
+        let mut i = 0;
+        while i < groups.len() {
+            let g = &groups[i];
+            i += 1;
+
+            match g[0].unwrap_or(0) {
+                0 => *f = Format::default(),
+                1 => f.bold = true,
+                2 => f.dim = true,
+                3 => f.italic = true,
+                4 => {
+                    f.underline = match g.get(1).copied().flatten() {
+                        None | Some(1) => Underline::Single,
+                        Some(2) => Underline::Double,
+                        Some(3) => Underline::Curly,
+                        Some(4) => Underline::Dotted,
+                        Some(5) => Underline::Dashed,
+                        _ => Underline::None,
+                    }
+                }
+                5 | 6 => f.blink = true,
+                7 => f.reverse = true,
+                8 => f.hidden = true,
+                9 => f.strikethrough = true,
+                21 => f.underline = Underline::Double,
+                22 => {
+                    f.bold = false;
+                    f.dim = false;
+                }
+                23 => f.italic = false,
+                24 => f.underline = Underline::None,
+                25 => f.blink = false,
+                27 => f.reverse = false,
+                28 => f.hidden = false,
+                29 => f.strikethrough = false,
+                n @ 30..=37 => f.fg = Colour::C16((n - 30) as u8),
+                39 => f.fg = Colour::Default,
+                n @ 40..=47 => f.bg = Colour::C16((n - 40) as u8),
+                49 => f.bg = Colour::Default,
+                53 => f.overline = true,
+                55 => f.overline = false,
+                59 => f.underline_colour = Colour::Default,
+                n @ 90..=97 => f.fg = Colour::C16((n - 90 + 8) as u8),
+                n @ 100..=107 => f.bg = Colour::C16((n - 100 + 8) as u8),
+                n @ (38 | 48 | 58) => {
+                    let c = if g.len() > 1 {
+                        extended_colour(&g[1..])
+                    } else {
+                        /*
+                         * The older form, in which the colour is given in the
+                         * parameters that follow.
+                         */
+                        let rest: Vec<_> =
+                            groups[i..].iter().map(|g| g[0]).collect();
+                        let n = match rest.first() {
+                            Some(Some(5)) => 2,
+                            Some(Some(2)) => 4,
+                            _ => 0,
+                        }
+                        .min(rest.len());
+                        i += n;
+                        extended_colour(&rest[..n])
+                    };
+
+                    if let Some(c) = c {
+                        match n {
+                            38 => f.fg = c,
+                            48 => f.bg = c,
+                            _ => f.underline_colour = c,
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/**
+ * Interpret the arguments of an extended colour: either "5;n" for an entry in
+ * the 256 colour palette, or "2;r;g;b" for a direct colour.  In the colon
+ * separated form, the direct colour may also have a colour space identifier
+ * before the components, which we ignore.
+ */
+fn extended_colour(args: &[Option<u32>]) -> Option<Colour> {
+    let byte = |v: Option<u32>| v.unwrap_or(0).min(255) as u8;
+
+    match args {
+        [Some(5), n, ..] => Some(Colour::C256(byte(*n))),
+        [Some(2), _, r, g, b, ..] if args.len() >= 5 => {
+            Some(Colour::RGB(byte(*r), byte(*g), byte(*b)))
+        }
+        [Some(2), r, g, b, ..] => {
+            Some(Colour::RGB(byte(*r), byte(*g), byte(*b)))
+        }
+        _ => None,
+    }
+}
+
+/**
+ * The length of a UTF-8 sequence, given its leading byte.
+ */
+fn utf8_len(b: u8) -> usize {
+    match b {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        _ => 4,
+    }
+}
+
+impl Text {
+    /**
+     * Parse output containing ANSI escape sequences; see AnsiParser.
+     */
+    pub fn from_ansi(buf: &[u8]) -> Text {
+        let mut p = AnsiParser::new();
+        p.feed(buf);
+        p.finish()
+    }
+}
+
+impl Region {
+    /**
+     * Draw output containing ANSI escape sequences within a rectangle, as
+     * directed by the layout.  Returns the number of lines used.
+     */
+    pub fn ansi(&mut self, r: Rect, buf: &[u8], layout: &TextLayout) -> usize {
+        self.rich_layout(r, &Text::from_ansi(buf), layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(t: &Text) -> Vec<String> {
+        t.lines.iter().map(|l| l.plain()).collect()
+    }
+
+    fn format_after(buf: &[u8]) -> Format {
+        let mut p = AnsiParser::new();
+        p.feed(buf);
+        *p.format()
+    }
+
+    fn colours(buf: &[u8]) -> (Colour, Colour, Colour) {
+        let f = format_after(buf);
+        (f.fg, f.bg, f.underline_colour)
+    }
+
+    #[test]
+    fn extended_colours() {
+        let d = Colour::Default;
+        let rgb = Colour::RGB(1, 2, 3);
+        let idx = Colour::C256(208);
+
+        assert_eq!(colours(b"\x1b[38;5;208m"), (idx, d, d));
+        assert_eq!(colours(b"\x1b[48;5;208m"), (d, idx, d));
+        assert_eq!(colours(b"\x1b[58;5;208m"), (d, d, idx));
+        assert_eq!(colours(b"\x1b[38:5:208m"), (idx, d, d));
+
+        assert_eq!(colours(b"\x1b[38;2;1;2;3m"), (rgb, d, d));
+        assert_eq!(colours(b"\x1b[48;2;1;2;3m"), (d, rgb, d));
+        assert_eq!(colours(b"\x1b[58;2;1;2;3m"), (d, d, rgb));
+
+        /*
+         * The colon separated form, with and without a colour space.
+         */
+        assert_eq!(colours(b"\x1b[38:2::1:2:3m"), (rgb, d, d));
+        assert_eq!(colours(b"\x1b[48:2::1:2:3m"), (d, rgb, d));
+        assert_eq!(colours(b"\x1b[58:2::1:2:3m"), (d, d, rgb));
+        assert_eq!(colours(b"\x1b[38:2:7:1:2:3m"), (rgb, d, d));
+        assert_eq!(colours(b"\x1b[48:2:1:2:3m"), (d, rgb, d));
+
+        assert_eq!(colours(b"\x1b[38;2;300;2;3m").0, Colour::RGB(255, 2, 3));
+        assert_eq!(
+            colours(b"\x1b[31;42m"),
+            (Colour::C16(1), Colour::C16(2), d)
+        );
+        assert_eq!(
+            colours(b"\x1b[91;102m"),
+            (Colour::C16(9), Colour::C16(10), d)
+        );
+    }
+
+    #[test]
+    fn parameters_after_extended_colours() {
+        let f = format_after(b"\x1b[38;5;9;1;48;2;1;2;3;4m");
+        assert_eq!(f.fg, Colour::C256(9));
+        assert_eq!(f.bg, Colour::RGB(1, 2, 3));
+        assert!(f.bold);
+        assert!(f.underline == Underline::Single);
+
+        /*
+         * Extended colours with missing arguments are ignored, along with
+         * whatever arguments are present.
+         */
+        assert_eq!(colours(b"\x1b[38;5m").0, Colour::Default);
+        assert_eq!(colours(b"\x1b[38;2;1;2m").0, Colour::Default);
+        assert_eq!(colours(b"\x1b[38;7;1m").0, Colour::Default);
+        assert!(format_after(b"\x1b[38;7;1m").bold);
+        assert_eq!(colours(b"\x1b[38:2:1m").0, Colour::Default);
+    }
+
+    #[test]
+    fn underline_styles() {
+        let underline = |buf: &[u8]| format_after(buf).underline;
+        assert!(underline(b"\x1b[4m") == Underline::Single);
+        assert!(underline(b"\x1b[4:1m") == Underline::Single);
+        assert!(underline(b"\x1b[4:2m") == Underline::Double);
+        assert!(underline(b"\x1b[4:3m") == Underline::Curly);
+        assert!(underline(b"\x1b[4:4m") == Underline::Dotted);
+        assert!(underline(b"\x1b[4:5m") == Underline::Dashed);
+        assert!(underline(b"\x1b[4:3m\x1b[4:0m") == Underline::None);
+        assert!(underline(b"\x1b[21m") == Underline::Double);
+        assert!(underline(b"\x1b[4:3;24m") == Underline::None);
+
+        /*
+         * The colon applies only within a parameter.
+         */
+        assert!(underline(b"\x1b[4;3m") == Underline::Single);
+        assert!(format_after(b"\x1b[4;3m").italic);
+    }
+
+    #[test]
+    fn other_sequences_ignored() {
+        let t =
+            Text::from_ansi(b"a\x1b[2Jb\x1b[?25lc\x1b[>4;1md\x1b[1 qe\x1b7f");
+        assert_eq!(plain(&t), ["abcdef"]);
+        assert!(t.lines[0].spans.iter().all(|s| s.format == Format::default()));
+    }
+
+    #[test]
+    fn split_input() {
+        let input = "\x1b[1;38;2;10;20;30ma中b\x1b[0m\x1b]0;x\x07c€\td";
+        let whole = Text::from_ansi(input.as_bytes());
+        assert_eq!(plain(&whole), ["a中bc€  d"]);
+
+        /*
+         * Any split gives the same result, whether it falls within a control
+         * sequence or a UTF-8 sequence.
+         */
+        for n in 0..=input.len() {
+            let mut p = AnsiParser::new();
+            p.feed(&input.as_bytes()[..n]);
+            p.feed(&input.as_bytes()[n..]);
+            assert!(p.finish() == whole, "split at {}", n);
+        }
+
+        let mut p = AnsiParser::new();
+        input.bytes().for_each(|b| p.feed(&[b]));
+        assert!(p.finish() == whole);
+
+        let mut p = AnsiParser::new();
+        p.feed(b"\x1b[3");
+        p.feed(b"1");
+        assert_eq!(p.format().fg, Colour::Default);
+        p.feed(b"m");
+        assert_eq!(p.format().fg, Colour::C16(1));
+    }
+
+    #[test]
+    fn strings_terminated() {
+        let bel = Text::from_ansi(b"a\x1b]0;title\x07b");
+        assert_eq!(plain(&bel), ["ab"]);
+
+        let st = Text::from_ansi(b"a\x1b]8;;http://x/\x1b\\b\x1b]8;;\x1b\\c");
+        assert_eq!(plain(&st), ["abc"]);
+
+        let dcs = Text::from_ansi(b"a\x1bPq#0;1\x1b\\b\x1b_x\x1b\\c");
+        assert_eq!(plain(&dcs), ["abc"]);
+
+        /*
+         * An escape that does not form ST aborts the string and starts a new
+         * sequence.
+         */
+        let aborted = Text::from_ansi(b"a\x1b]0;title\x1b[1mb");
+        assert_eq!(plain(&aborted), ["ab"]);
+        assert!(aborted.lines[0].spans[1].format.bold);
+
+        /*
+         * Text inside a string is never shown, even across a newline.
+         */
+        let t = Text::from_ansi(b"a\x1b]0;x\ny\x07b");
+        assert_eq!(plain(&t), ["ab"]);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let t = Text::from_ansi(b"a\xE4\xB8b");
+        assert_eq!(plain(&t), ["a\u{FFFD}b"]);
+
+        let t = Text::from_ansi(b"a\xE4\xB8\x1b[1mb");
+        assert_eq!(plain(&t), ["a\u{FFFD}b"]);
+        assert!(t.lines[0].spans[1].format.bold);
+
+        let t = Text::from_ansi(b"a\x80b\xFFc\xC0d");
+        assert_eq!(plain(&t), ["a\u{FFFD}b\u{FFFD}c\u{FFFD}d"]);
+
+        /*
+         * Encoded surrogates are not valid UTF-8.
+         */
+        let t = Text::from_ansi(b"\xED\xA0\x80a");
+        assert_eq!(plain(&t), ["\u{FFFD}a"]);
+
+        /*
+         * A sequence cut short by the end of the input.
+         */
+        let mut p = AnsiParser::new();
+        p.feed(b"a\xF0\x9F\x98");
+        assert_eq!(plain(p.text()), ["a"]);
+        assert_eq!(plain(&p.finish()), ["a\u{FFFD}"]);
+    }
+
+    #[test]
+    fn tabs_and_newlines() {
+        let t = Text::from_ansi(b"a\tb\n12345678\tc\n\td\r\n");
+        assert_eq!(
+            plain(&t),
+            ["a       b", "12345678        c", "        d", ""]
+        );
+
+        /*
+         * Tab stops are measured in columns, not characters.
+         */
+        let t = Text::from_ansi("中\tx\ne\u{301}\tx".as_bytes());
+        assert_eq!(plain(&t), ["中      x", "e\u{301}       x"]);
+
+        /*
+         * The spaces take on the current format.
+         */
+        let t = Text::from_ansi(b"a\x1b[7m\tb");
+        assert_eq!(plain(&t), ["a       b"]);
+        assert_eq!(t.lines[0].spans[1].text, "       b");
+        assert!(t.lines[0].spans[1].format.reverse);
+    }
+}
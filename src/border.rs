@@ -0,0 +1,484 @@
+use super::grapheme::cluster_width;
+use super::region::*;
+use unicode_segmentation::UnicodeSegmentation;
+
+/**
+ * The set of characters used to draw lines and boxes.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Border {
+    Ascii,
+    #[default]
+    Single,
+    Double,
+    Heavy,
+    Rounded,
+    Dashed,
+}
+
+/*
+ * A piece of line within a cell is described by the weight of each of its four
+ * arms, in the order: up, right, down, left.  A weight of 0 means there is no
+ * arm in that direction.
+ */
+type Arms = [u8; 4];
+
+const NONE: u8 = 0;
+const LIGHT: u8 = 1;
+const HEAVY: u8 = 2;
+const DOUBLE: u8 = 3;
+
+/*
+ * The box-drawing characters from U+2500 to U+257F, with their arms.  When
+ * searching by arms, the first match wins, so the solid lines are listed ahead
+ * of the dashed lines and rounded corners.
+ */
+#[rustfmt::skip]
+const LINES: &[(char, Arms)] = &[
+    ('─', [0, 1, 0, 1]), ('━', [0, 2, 0, 2]),
+    ('│', [1, 0, 1, 0]), ('┃', [2, 0, 2, 0]),
+    ('┌', [0, 1, 1, 0]), ('┍', [0, 2, 1, 0]), ('┎', [0, 1, 2, 0]),
+    ('┏', [0, 2, 2, 0]), ('┐', [0, 0, 1, 1]), ('┑', [0, 0, 1, 2]),
+    ('┒', [0, 0, 2, 1]), ('┓', [0, 0, 2, 2]), ('└', [1, 1, 0, 0]),
+    ('┕', [1, 2, 0, 0]), ('┖', [2, 1, 0, 0]), ('┗', [2, 2, 0, 0]),
+    ('┘', [1, 0, 0, 1]), ('┙', [1, 0, 0, 2]), ('┚', [2, 0, 0, 1]),
+    ('┛', [2, 0, 0, 2]), ('├', [1, 1, 1, 0]), ('┝', [1, 2, 1, 0]),
+    ('┞', [2, 1, 1, 0]), ('┟', [1, 1, 2, 0]), ('┠', [2, 1, 2, 0]),
+    ('┡', [2, 2, 1, 0]), ('┢', [1, 2, 2, 0]), ('┣', [2, 2, 2, 0]),
+    ('┤', [1, 0, 1, 1]), ('┥', [1, 0, 1, 2]), ('┦', [2, 0, 1, 1]),
+    ('┧', [1, 0, 2, 1]), ('┨', [2, 0, 2, 1]), ('┩', [2, 0, 1, 2]),
+    ('┪', [1, 0, 2, 2]), ('┫', [2, 0, 2, 2]), ('┬', [0, 1, 1, 1]),
+    ('┭', [0, 1, 1, 2]), ('┮', [0, 2, 1, 1]), ('┯', [0, 2, 1, 2]),
+    ('┰', [0, 1, 2, 1]), ('┱', [0, 1, 2, 2]), ('┲', [0, 2, 2, 1]),
+    ('┳', [0, 2, 2, 2]), ('┴', [1, 1, 0, 1]), ('┵', [1, 1, 0, 2]),
+    ('┶', [1, 2, 0, 1]), ('┷', [1, 2, 0, 2]), ('┸', [2, 1, 0, 1]),
+    ('┹', [2, 1, 0, 2]), ('┺', [2, 2, 0, 1]), ('┻', [2, 2, 0, 2]),
+    ('┼', [1, 1, 1, 1]), ('┽', [1, 1, 1, 2]), ('┾', [1, 2, 1, 1]),
+    ('┿', [1, 2, 1, 2]), ('╀', [2, 1, 1, 1]), ('╁', [1, 1, 2, 1]),
+    ('╂', [2, 1, 2, 1]), ('╃', [2, 1, 1, 2]), ('╄', [2, 2, 1, 1]),
+    ('╅', [1, 1, 2, 2]), ('╆', [1, 2, 2, 1]), ('╇', [2, 2, 1, 2]),
+    ('╈', [1, 2, 2, 2]), ('╉', [2, 1, 2, 2]), ('╊', [2, 2, 2, 1]),
+    ('╋', [2, 2, 2, 2]),
+    ('═', [0, 3, 0, 3]), ('║', [3, 0, 3, 0]), ('╒', [0, 3, 1, 0]),
+    ('╓', [0, 1, 3, 0]), ('╔', [0, 3, 3, 0]), ('╕', [0, 0, 1, 3]),
+    ('╖', [0, 0, 3, 1]), ('╗', [0, 0, 3, 3]), ('╘', [1, 3, 0, 0]),
+    ('╙', [3, 1, 0, 0]), ('╚', [3, 3, 0, 0]), ('╛', [1, 0, 0, 3]),
+    ('╜', [3, 0, 0, 1]), ('╝', [3, 0, 0, 3]), ('╞', [1, 3, 1, 0]),
+    ('╟', [3, 1, 3, 0]), ('╠', [3, 3, 3, 0]), ('╡', [1, 0, 1, 3]),
+    ('╢', [3, 0, 3, 1]), ('╣', [3, 0, 3, 3]), ('╤', [0, 3, 1, 3]),
+    ('╥', [0, 1, 3, 1]), ('╦', [0, 3, 3, 3]), ('╧', [1, 3, 0, 3]),
+    ('╨', [3, 1, 0, 1]), ('╩', [3, 3, 0, 3]), ('╪', [1, 3, 1, 3]),
+    ('╫', [3, 1, 3, 1]), ('╬', [3, 3, 3, 3]),
+    ('╴', [0, 0, 0, 1]), ('╵', [1, 0, 0, 0]), ('╶', [0, 1, 0, 0]),
+    ('╷', [0, 0, 1, 0]), ('╸', [0, 0, 0, 2]), ('╹', [2, 0, 0, 0]),
+    ('╺', [0, 2, 0, 0]), ('╻', [0, 0, 2, 0]), ('╼', [0, 2, 0, 1]),
+    ('╽', [1, 0, 2, 0]), ('╾', [0, 1, 0, 2]), ('╿', [2, 0, 1, 0]),
+    ('┄', [0, 1, 0, 1]), ('┅', [0, 2, 0, 2]), ('┆', [1, 0, 1, 0]),
+    ('┇', [2, 0, 2, 0]), ('┈', [0, 1, 0, 1]), ('┉', [0, 2, 0, 2]),
+    ('┊', [1, 0, 1, 0]), ('┋', [2, 0, 2, 0]), ('╌', [0, 1, 0, 1]),
+    ('╍', [0, 2, 0, 2]), ('╎', [1, 0, 1, 0]), ('╏', [2, 0, 2, 0]),
+    ('╭', [0, 1, 1, 0]), ('╮', [0, 0, 1, 1]), ('╯', [1, 0, 0, 1]),
+    ('╰', [1, 1, 0, 0]),
+];
+
+fn arms_of(s: &str) -> Option<Arms> {
+    let mut chars = s.chars();
+    let ch = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+
+    LINES.iter().find(|(c, _)| *c == ch).map(|(_, a)| *a)
+}
+
+fn glyph(arms: Arms) -> Option<char> {
+    LINES.iter().find(|(_, a)| *a == arms).map(|(c, _)| *c)
+}
+
+fn ascii_arms(s: &str) -> Option<Arms> {
+    match s {
+        "-" => Some([0, 1, 0, 1]),
+        "|" => Some([1, 0, 1, 0]),
+        "+" => Some([1, 1, 1, 1]),
+        _ => None,
+    }
+}
+
+fn ascii_glyph(arms: Arms) -> char {
+    let horizontal = arms[1] != NONE || arms[3] != NONE;
+    let vertical = arms[0] != NONE || arms[2] != NONE;
+    match (horizontal, vertical) {
+        (true, false) => '-',
+        (false, true) => '|',
+        _ => '+',
+    }
+}
+
+impl Border {
+    fn weight(self) -> u8 {
+        match self {
+            Border::Heavy => HEAVY,
+            Border::Double => DOUBLE,
+            _ => LIGHT,
+        }
+    }
+
+    /**
+     * Build the arms for a piece of line in this style.
+     */
+    fn arms(self, up: bool, right: bool, down: bool, left: bool) -> Arms {
+        let w = |on: bool| if on { self.weight() } else { NONE };
+        [w(up), w(right), w(down), w(left)]
+    }
+
+    /**
+     * The character for a piece of line in this style, where it does not
+     * meet any other line.
+     */
+    fn glyph(self, arms: Arms) -> char {
+        let special = match (self, arms) {
+            (Border::Ascii, _) => Some(ascii_glyph(arms)),
+            (Border::Rounded, [0, 1, 1, 0]) => Some('╭'),
+            (Border::Rounded, [0, 0, 1, 1]) => Some('╮'),
+            (Border::Rounded, [1, 0, 0, 1]) => Some('╯'),
+            (Border::Rounded, [1, 1, 0, 0]) => Some('╰'),
+            (Border::Dashed, [0, 1, 0, 1]) => Some('┄'),
+            (Border::Dashed, [1, 0, 1, 0]) => Some('┆'),
+            _ => None,
+        };
+
+        special.or_else(|| glyph(arms)).unwrap_or(' ')
+    }
+}
+
+impl Region {
+    /**
+     * Draw a piece of line at (x, y).  If the cell already holds a piece of
+     * line, the two are merged into a junction; in that case, "inward" gives
+     * the arms to contribute, so that a line ending against another line
+     * forms a tee rather than a cross.
+     */
+    fn line_at(
+        &mut self,
+        x: usize,
+        y: usize,
+        arms: Arms,
+        inward: Arms,
+        border: Border,
+        f: &Format,
+    ) {
+        let old = match self.cell(x, y) {
+            Some(c) if c.is_continuation() => None,
+            Some(c) if border == Border::Ascii => ascii_arms(c.contents()),
+            Some(c) => arms_of(c.contents()),
+            None => return,
+        };
+
+        let ch = match old {
+            None => border.glyph(arms),
+            Some(old) => {
+                let mut merged = old;
+                for (m, &n) in merged.iter_mut().zip(inward.iter()) {
+                    if n != NONE {
+                        *m = n;
+                    }
+                }
+
+                if merged == inward {
+                    border.glyph(inward)
+                } else if border == Border::Ascii {
+                    ascii_glyph(merged)
+                } else {
+                    /*
+                     * Not every combination of weights has a character of
+                     * its own (e.g., heavy lines do not meet double lines),
+                     * in which case the new line's weight is used throughout.
+                     */
+                    let w = border.weight();
+                    glyph(merged)
+                        .or_else(|| glyph(merged.map(|a| a.min(1) * w)))
+                        .unwrap_or_else(|| border.glyph(arms))
+                }
+            }
+        };
+
+        self.chrf(x, y, ch, f);
+    }
+
+    /**
+     * Draw a horizontal line of "len" cells, starting at (x, y) and extending
+     * to the right.  Where it meets existing lines, junctions are formed.
+     */
+    pub fn hline(
+        &mut self,
+        x: usize,
+        y: usize,
+        len: usize,
+        border: Border,
+        f: &Format,
+    ) {
+        let arms = border.arms(false, true, false, true);
+        for i in 0..len.min(self.width().saturating_sub(x)) {
+            let inward = border.arms(false, i + 1 < len, false, i > 0);
+            self.line_at(x + i, y, arms, inward, border, f);
+        }
+    }
+
+    /**
+     * Draw a vertical line of "len" cells, starting at (x, y) and extending
+     * downward.  Where it meets existing lines, junctions are formed.
+     */
+    pub fn vline(
+        &mut self,
+        x: usize,
+        y: usize,
+        len: usize,
+        border: Border,
+        f: &Format,
+    ) {
+        let arms = border.arms(true, false, true, false);
+        for i in 0..len.min(self.height().saturating_sub(y)) {
+            let inward = border.arms(i > 0, false, i + 1 < len, false);
+            self.line_at(x, y + i, arms, inward, border, f);
+        }
+    }
+
+    /**
+     * Draw a box around the edge of a rectangle.  The interior is left as it
+     * is.
+     */
+    pub fn rect(&mut self, r: Rect, border: Border, f: &Format) {
+        if r.is_empty() {
+            return;
+        }
+        if r.height == 1 {
+            return self.hline(r.x, r.y, r.width, border, f);
+        }
+        if r.width == 1 {
+            return self.vline(r.x, r.y, r.height, border, f);
+        }
+
+        let (right, bottom) = (r.x + r.width - 1, r.y + r.height - 1);
+        let mut piece = |x, y, arms| self.line_at(x, y, arms, arms, border, f);
+
+        piece(r.x, r.y, border.arms(false, true, true, false));
+        piece(right, r.y, border.arms(false, false, true, true));
+        piece(r.x, bottom, border.arms(true, true, false, false));
+        piece(right, bottom, border.arms(true, false, false, true));
+
+        let h = border.arms(false, true, false, true);
+        for x in (r.x + 1)..right {
+            piece(x, r.y, h);
+            piece(x, bottom, h);
+        }
+        let v = border.arms(true, false, true, false);
+        for y in (r.y + 1)..bottom {
+            piece(r.x, y, v);
+            piece(right, y, v);
+        }
+    }
+
+    /**
+     * Draw a box around the edge of a rectangle, with a title set into the
+     * top edge.  A title that is too long to fit is truncated.
+     */
+    pub fn rect_title(
+        &mut self,
+        r: Rect,
+        border: Border,
+        f: &Format,
+        title: &str,
+        align: Align,
+    ) {
+        self.rect(r, border, f);
+        if r.width < 4 {
+            return;
+        }
+
+        /*
+         * Leave the corners, and a space either side of the title.  Marks at
+         * the start of the title would combine with the leading space, and
+         * control characters have no width, so neither is counted.
+         */
+        let avail = r.width - 4;
+        let mut start = None;
+        let mut end = 0;
+        let mut tw = 0;
+        for (i, g) in title.grapheme_indices(true) {
+            let w = cluster_width(g);
+            if w == 0 && start.is_none() {
+                continue;
+            }
+            if tw + w > avail {
+                break;
+            }
+            start.get_or_insert(i);
+            tw += w;
+            end = i + g.len();
+        }
+        let Some(start) = start else {
+            return;
+        };
+
+        let slack = avail.saturating_sub(tw);
+        let x = r.x
+            + 1
+            + match align {
+                Align::Left => 0,
+                Align::Centre => slack / 2,
+                Align::Right => slack,
+            };
+        self.strf(x, r.y, &format!(" {} ", &title[start..end]), f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(r: &Region, y: usize) -> String {
+        (0..r.width())
+            .filter_map(|x| r.cell(x, y))
+            .filter(|c| !c.is_continuation())
+            .map(|c| c.contents())
+            .collect()
+    }
+
+    fn titled(width: usize, title: &str, align: Align) -> String {
+        let mut r = Region::new(width, 3);
+        let f = Format::default();
+        r.rect_title(
+            Rect::new(0, 0, width, 3),
+            Border::Single,
+            &f,
+            title,
+            align,
+        );
+        row(&r, 0)
+    }
+
+    #[test]
+    fn title_alignment() {
+        assert_eq!(titled(8, "ab", Align::Left), "┌ ab ──┐");
+        assert_eq!(titled(8, "ab", Align::Centre), "┌─ ab ─┐");
+        assert_eq!(titled(8, "ab", Align::Right), "┌── ab ┐");
+    }
+
+    #[test]
+    fn title_truncated() {
+        assert_eq!(titled(8, "abcdef", Align::Left), "┌ abcd ┐");
+        assert_eq!(titled(8, "中文字", Align::Right), "┌ 中文 ┐");
+        assert_eq!(titled(9, "中文字", Align::Right), "┌─ 中文 ┐");
+        assert_eq!(titled(5, "中", Align::Left), "┌───┐");
+        assert_eq!(titled(4, "ab", Align::Left), "┌──┐");
+    }
+
+    #[test]
+    fn title_zero_width() {
+        assert_eq!(titled(8, "\u{301}ab", Align::Left), "┌ ab ──┐");
+        assert_eq!(titled(8, "\tab", Align::Right), "┌── ab ┐");
+        assert_eq!(titled(8, "\r\n", Align::Centre), "┌──────┐");
+
+        /*
+         * Titles that cannot be shown in a narrow box are ignored.
+         */
+        for width in 0..4 {
+            for title in ["\t", "\u{301}", "\r\n", "a"] {
+                let plain = titled(width, "", Align::Left);
+                assert_eq!(titled(width, title, Align::Right), plain);
+            }
+        }
+    }
+
+    fn rows(r: &Region) -> Vec<String> {
+        (0..r.height()).map(|y| row(r, y)).collect()
+    }
+
+    #[test]
+    fn lines_cross() {
+        let f = Format::default();
+        let mut r = Region::new(7, 5);
+        r.hline(0, 2, 7, Border::Single, &f);
+        r.vline(3, 0, 5, Border::Single, &f);
+        assert_eq!(
+            rows(&r),
+            ["   │   ", "   │   ", "───┼───", "   │   ", "   │   "]
+        );
+    }
+
+    #[test]
+    fn lines_end_in_tees() {
+        let f = Format::default();
+        let mut r = Region::new(7, 5);
+        r.hline(0, 2, 7, Border::Single, &f);
+        r.vline(1, 0, 3, Border::Single, &f);
+        r.vline(5, 2, 3, Border::Single, &f);
+        assert_eq!(
+            rows(&r),
+            [" │     ", " │     ", "─┴───┬─", "     │ ", "     │ "]
+        );
+
+        let mut r = Region::new(7, 5);
+        r.vline(3, 0, 5, Border::Single, &f);
+        r.hline(0, 1, 4, Border::Single, &f);
+        r.hline(3, 3, 4, Border::Single, &f);
+        assert_eq!(
+            rows(&r),
+            ["   │   ", "───┤   ", "   │   ", "   ├───", "   │   "]
+        );
+    }
+
+    #[test]
+    fn lines_divide_box() {
+        let f = Format::default();
+        let mut r = Region::new(7, 5);
+        r.rect(Rect::new(0, 0, 7, 5), Border::Single, &f);
+        r.hline(0, 2, 7, Border::Single, &f);
+        r.vline(3, 0, 5, Border::Single, &f);
+        assert_eq!(
+            rows(&r),
+            ["┌──┬──┐", "│  │  │", "├──┼──┤", "│  │  │", "└──┴──┘"]
+        );
+
+        /*
+         * Drawing the box again leaves the junctions alone.
+         */
+        r.rect(Rect::new(0, 0, 7, 5), Border::Single, &f);
+        assert_eq!(
+            rows(&r),
+            ["┌──┬──┐", "│  │  │", "├──┼──┤", "│  │  │", "└──┴──┘"]
+        );
+    }
+
+    #[test]
+    fn boxes_overlap() {
+        let f = Format::default();
+        let mut r = Region::new(7, 5);
+        r.rect(Rect::new(0, 0, 5, 3), Border::Single, &f);
+        r.rect(Rect::new(2, 2, 5, 3), Border::Single, &f);
+        assert_eq!(
+            rows(&r),
+            ["┌───┐  ", "│   │  ", "└─┬─┴─┐", "  │   │", "  └───┘"]
+        );
+    }
+
+    #[test]
+    fn junctions_mix_weights() {
+        let f = Format::default();
+        let mut r = Region::new(7, 3);
+        r.rect(Rect::new(0, 0, 7, 3), Border::Single, &f);
+        r.vline(3, 0, 3, Border::Double, &f);
+        r.hline(0, 1, 7, Border::Heavy, &f);
+        assert_eq!(rows(&r), ["┌──╥──┐", "┝━━╋━━┥", "└──╨──┘"]);
+
+        let mut r = Region::new(7, 3);
+        r.rect(Rect::new(0, 0, 7, 3), Border::Rounded, &f);
+        r.vline(3, 0, 3, Border::Single, &f);
+        assert_eq!(rows(&r), ["╭──┬──╮", "│  │  │", "╰──┴──╯"]);
+
+        let mut r = Region::new(7, 3);
+        r.rect(Rect::new(0, 0, 7, 3), Border::Ascii, &f);
+        r.vline(3, 0, 3, Border::Ascii, &f);
+        assert_eq!(rows(&r), ["+--+--+", "|  |  |", "+--+--+"]);
+    }
+}
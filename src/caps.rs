@@ -0,0 +1,546 @@
+use super::colour::ColourDepth;
+use super::mode::ModeReport;
+use super::terminfo::*;
+use std::ops::Range;
+
+/**
+ * The DEC private mode that, while set, has the terminal hold back its display
+ * until the mode is reset, so that a frame appears all at once.
+ */
+const MODE_SYNCHRONIZED_OUTPUT: u32 = 2026;
+
+/**
+ * What we know about the features of the terminal on which we are drawing.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    /**
+     * Whether it is safe to draw in the bottom-right cell of the screen; i.e.,
+     * the terminal either has the VT100 line glitch ("xenl") or does not
+     * automatically wrap at the right margin.
+     */
+    pub line_glitch: bool,
+    pub colour_depth: ColourDepth,
+    pub italics: bool,
+    pub alternate_screen: bool,
+    /**
+     * Whether the terminal can set a scrolling region (DECSTBM), scroll it
+     * (SU and SD), and insert and delete lines.
+     */
+    pub scroll_region: bool,
+    /**
+     * Whether the terminal can erase a number of characters at once (ECH).
+     */
+    pub erase_chars: bool,
+    /**
+     * Whether the terminal can repeat the preceding character (REP).
+     */
+    pub repeat_char: bool,
+    /**
+     * Whether erased cells take on the current background colour ("bce"),
+     * rather than the default.
+     */
+    pub back_colour_erase: bool,
+    /**
+     * Whether the terminal can move the cursor to a given row without
+     * changing its column (VPA).
+     */
+    pub row_address: bool,
+    /**
+     * Whether the terminal supports synchronized output (mode 2026).  This is
+     * rarely described by terminfo, so it is best detected by querying the
+     * terminal; see Capabilities::detect_synchronized_output().
+     */
+    pub synchronized_output: bool,
+}
+
+impl Default for Capabilities {
+    /**
+     * The defaults describe a modern xterm-like terminal, which is what Draw
+     * assumes when it is not told otherwise.  Optimisations that depend on
+     * less widely supported sequences are left disabled, and are enabled only
+     * once the terminal is known to support them.
+     */
+    fn default() -> Self {
+        Capabilities {
+            line_glitch: true,
+            colour_depth: ColourDepth::TrueColour,
+            italics: true,
+            alternate_screen: true,
+            scroll_region: false,
+            erase_chars: false,
+            repeat_char: false,
+            back_colour_erase: false,
+            row_address: false,
+            synchronized_output: false,
+        }
+    }
+}
+
+impl Capabilities {
+    /**
+     * The query (DECRQM) that asks the terminal to report whether it supports
+     * synchronized output.
+     */
+    pub const SYNCHRONIZED_OUTPUT_QUERY: &'static str = "\x1b[?2026$p";
+
+    /**
+     * Determine the capabilities of the terminal described by the TERM,
+     * COLORTERM, and NO_COLOR environment variables.
+     */
+    pub fn from_env() -> Capabilities {
+        let var = |n: &str| std::env::var(n).ok().filter(|v| !v.is_empty());
+
+        Capabilities::from_vars(
+            var("TERM").as_deref(),
+            var("COLORTERM").as_deref(),
+            var("NO_COLOR").is_some(),
+        )
+    }
+
+    /**
+     * Determine the capabilities of a terminal given the values of the
+     * environment variables that describe it.  The terminfo database is
+     * consulted for the terminal type, if an entry can be found.
+     */
+    pub fn from_vars(
+        term: Option<&str>,
+        colorterm: Option<&str>,
+        no_color: bool,
+    ) -> Capabilities {
+        Capabilities::from_vars_with(term, colorterm, no_color, Terminfo::load)
+    }
+
+    /**
+     * As for from_vars(), but with the means of finding a terminfo entry
+     * supplied by the caller, so that tests need not depend on the entries
+     * installed on the system.
+     */
+    fn from_vars_with(
+        term: Option<&str>,
+        colorterm: Option<&str>,
+        no_color: bool,
+        load: impl Fn(&str) -> std::io::Result<Terminfo>,
+    ) -> Capabilities {
+        let mut caps = match term {
+            Some(term) => match load(term) {
+                Ok(ti) => Capabilities::from(&ti),
+                Err(_) => Capabilities::guess(term),
+            },
+            None => Capabilities::guess("dumb"),
+        };
+
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            caps.colour_depth = ColourDepth::TrueColour;
+        }
+
+        if no_color {
+            /*
+             * See https://no-color.org: colour is disabled, but other
+             * attributes remain available.
+             */
+            caps.colour_depth = ColourDepth::Mono;
+        }
+
+        caps
+    }
+
+    /**
+     * Determine the capabilities of a terminal from a compiled terminfo entry,
+     * as would be found in /usr/share/terminfo.
+     */
+    pub fn from_terminfo(buf: &[u8]) -> std::io::Result<Capabilities> {
+        Ok(Capabilities::from(&Terminfo::parse(buf)?))
+    }
+
+    /**
+     * Having written SYNCHRONIZED_OUTPUT_QUERY to the terminal, look for the
+     * response in the input read from it.  If the response is found, update
+     * synchronized_output and return the range of bytes that the response
+     * occupies, so that it can be removed from the input.  A terminal that
+     * does not understand the query will not respond at all, so the caller
+     * should give up waiting at some point; e.g., after a short timeout, or
+     * once the response to a later Device Attributes query has arrived.
+     */
+    pub fn detect_synchronized_output(
+        &mut self,
+        input: &[u8],
+    ) -> Option<Range<usize>> {
+        let mut start = 0;
+        while let Some((report, range)) = ModeReport::find(&input[start..]) {
+            let range = (start + range.start)..(start + range.end);
+            if report.private && report.mode == MODE_SYNCHRONIZED_OUTPUT {
+                self.synchronized_output = report.state.is_supported();
+                return Some(range);
+            }
+            start = range.end;
+        }
+        None
+    }
+
+    /**
+     * If we have no terminfo entry, make an educated guess based on the name
+     * of the terminal type.
+     */
+    fn guess(term: &str) -> Capabilities {
+        if term == "dumb" {
+            return Capabilities {
+                line_glitch: false,
+                colour_depth: ColourDepth::Mono,
+                italics: false,
+                alternate_screen: false,
+                scroll_region: false,
+                erase_chars: false,
+                repeat_char: false,
+                back_colour_erase: false,
+                row_address: false,
+                synchronized_output: false,
+            };
+        }
+
+        let xtermish =
+            ["xterm", "screen", "tmux", "rxvt", "alacritty", "kitty"]
+                .iter()
+                .any(|pfx| term.starts_with(pfx));
+
+        Capabilities {
+            line_glitch: true,
+            colour_depth: if term.ends_with("-direct") {
+                ColourDepth::TrueColour
+            } else if term.contains("256color") {
+                ColourDepth::C256
+            } else if xtermish || term.starts_with("linux") {
+                ColourDepth::C8
+            } else {
+                ColourDepth::Mono
+            },
+            italics: xtermish,
+            alternate_screen: xtermish,
+            scroll_region: xtermish,
+            erase_chars: xtermish || term.starts_with("linux"),
+            repeat_char: term.starts_with("xterm"),
+            back_colour_erase: term.starts_with("xterm")
+                || term.starts_with("linux"),
+            row_address: xtermish || term.starts_with("linux"),
+            synchronized_output: false,
+        }
+    }
+}
+
+impl From<&Terminfo> for Capabilities {
+    fn from(ti: &Terminfo) -> Self {
+        let colours = ti.number(NUM_MAX_COLORS).unwrap_or(0);
+
+        /*
+         * There is no standard capability for direct colour support, so we
+         * check for the common extensions: "RGB", from ncurses, and "Tc",
+         * from tmux.
+         */
+        let truecolour = colours >= 1 << 24
+            || ti.has_extended("RGB")
+            || ti.has_extended("Tc");
+
+        Capabilities {
+            line_glitch: ti.bool(BOOL_EAT_NEWLINE_GLITCH)
+                || !ti.bool(BOOL_AUTO_RIGHT_MARGIN),
+            colour_depth: if truecolour {
+                ColourDepth::TrueColour
+            } else if colours >= 256 {
+                ColourDepth::C256
+            } else if colours >= 16 {
+                ColourDepth::C16
+            } else if colours >= 8 {
+                ColourDepth::C8
+            } else {
+                ColourDepth::Mono
+            },
+            italics: ti.string(STR_ENTER_ITALICS_MODE).is_some(),
+            alternate_screen: ti.string(STR_ENTER_CA_MODE).is_some()
+                && ti.string(STR_EXIT_CA_MODE).is_some(),
+            scroll_region: [
+                STR_CHANGE_SCROLL_REGION,
+                STR_PARM_INDEX,
+                STR_PARM_RINDEX,
+                STR_PARM_INSERT_LINE,
+                STR_PARM_DELETE_LINE,
+            ]
+            .iter()
+            .all(|&idx| ti.string(idx).is_some()),
+            erase_chars: ti.string(STR_ERASE_CHARS).is_some(),
+            repeat_char: ti.string(STR_REPEAT_CHAR).is_some(),
+            back_colour_erase: ti.bool(BOOL_BACK_COLOR_ERASE),
+            row_address: ti.string(STR_ROW_ADDRESS).is_some(),
+            /*
+             * "Sync" is an extension, from tmux, that gives the sequence to
+             * begin or end a synchronized update.
+             */
+            synchronized_output: ti.has_extended("Sync"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminfo::tests::Entry;
+    use std::io::{Error, ErrorKind};
+
+    fn missing(_: &str) -> std::io::Result<Terminfo> {
+        Err(Error::new(ErrorKind::NotFound, "no entry"))
+    }
+
+    /**
+     * An entry for a terminal with automatic margins and the VT100 line
+     * glitch, but with only the given number of colours.
+     */
+    fn entry(colours: i32) -> Entry {
+        let mut bools = vec![false; 29];
+        bools[BOOL_AUTO_RIGHT_MARGIN] = true;
+        bools[BOOL_EAT_NEWLINE_GLITCH] = true;
+
+        let mut numbers = vec![-1; 14];
+        numbers[NUM_MAX_COLORS] = colours;
+
+        Entry { wide: colours > 0x7FFF, bools, numbers, ..Entry::default() }
+    }
+
+    fn caps(e: &Entry) -> Capabilities {
+        Capabilities::from_terminfo(&e.encode()).unwrap()
+    }
+
+    #[test]
+    fn guess_xterm_256color() {
+        let caps = Capabilities::guess("xterm-256color");
+        assert_eq!(caps.colour_depth, ColourDepth::C256);
+        assert!(caps.line_glitch);
+        assert!(caps.italics);
+        assert!(caps.alternate_screen);
+        assert!(caps.scroll_region);
+        assert!(caps.erase_chars && caps.repeat_char);
+        assert!(caps.back_colour_erase);
+        assert!(caps.row_address);
+
+        assert_eq!(
+            Capabilities::guess("xterm-direct").colour_depth,
+            ColourDepth::TrueColour
+        );
+        assert_eq!(Capabilities::guess("xterm").colour_depth, ColourDepth::C8);
+    }
+
+    #[test]
+    fn guess_multiplexers_and_console() {
+        for term in ["tmux-256color", "screen-256color"] {
+            let caps = Capabilities::guess(term);
+            assert_eq!(caps.colour_depth, ColourDepth::C256);
+            assert!(caps.italics && caps.alternate_screen);
+            assert!(caps.erase_chars);
+            assert!(!caps.repeat_char && !caps.back_colour_erase);
+        }
+
+        let screen = Capabilities::guess("screen");
+        assert_eq!(screen.colour_depth, ColourDepth::C8);
+        assert!(screen.alternate_screen);
+
+        let linux = Capabilities::guess("linux");
+        assert_eq!(linux.colour_depth, ColourDepth::C8);
+        assert!(linux.line_glitch);
+        assert!(!linux.italics);
+        assert!(!linux.alternate_screen);
+        assert!(!linux.scroll_region);
+        assert!(linux.erase_chars && linux.back_colour_erase);
+        assert!(!linux.repeat_char);
+        assert!(linux.row_address);
+    }
+
+    #[test]
+    fn guess_unknown_and_dumb() {
+        let unknown = Capabilities::guess("vt52");
+        assert_eq!(unknown.colour_depth, ColourDepth::Mono);
+        assert!(unknown.line_glitch);
+        assert!(!unknown.italics);
+        assert!(!unknown.alternate_screen);
+        assert!(!unknown.scroll_region);
+        assert!(!unknown.erase_chars && !unknown.repeat_char);
+        assert!(!unknown.back_colour_erase);
+        assert!(!unknown.row_address);
+
+        let dumb = Capabilities::guess("dumb");
+        assert_eq!(dumb.colour_depth, ColourDepth::Mono);
+        assert!(!dumb.line_glitch);
+        assert!(!dumb.alternate_screen);
+        assert!(!dumb.scroll_region);
+        assert!(!dumb.row_address);
+    }
+
+    #[test]
+    fn from_vars_without_terminfo() {
+        let vars = |term, colorterm, no_color| {
+            Capabilities::from_vars_with(term, colorterm, no_color, missing)
+        };
+
+        assert_eq!(
+            vars(Some("xterm-256color"), None, false),
+            Capabilities::guess("xterm-256color")
+        );
+        assert_eq!(vars(None, None, false), Capabilities::guess("dumb"));
+        assert_eq!(
+            vars(Some("vt52"), None, false),
+            Capabilities::guess("vt52")
+        );
+
+        /*
+         * COLORTERM can only add colours, and NO_COLOR overrides it.
+         */
+        for colorterm in ["truecolor", "24bit"] {
+            assert_eq!(
+                vars(Some("xterm-256color"), Some(colorterm), false)
+                    .colour_depth,
+                ColourDepth::TrueColour
+            );
+        }
+        assert_eq!(
+            vars(Some("xterm-256color"), Some("yes"), false).colour_depth,
+            ColourDepth::C256
+        );
+
+        let none = vars(Some("xterm-256color"), Some("truecolor"), true);
+        assert_eq!(none.colour_depth, ColourDepth::Mono);
+        assert!(none.italics);
+    }
+
+    #[test]
+    fn from_vars_prefers_terminfo() {
+        let e = entry(256);
+        let load = |term: &str| {
+            assert_eq!(term, "xterm");
+            Terminfo::parse(&e.encode())
+        };
+
+        /*
+         * The name alone would suggest only 8 colours and italics.
+         */
+        let caps =
+            Capabilities::from_vars_with(Some("xterm"), None, false, load);
+        assert_eq!(caps.colour_depth, ColourDepth::C256);
+        assert!(!caps.italics);
+
+        let caps = Capabilities::from_vars_with(
+            Some("xterm"),
+            Some("truecolor"),
+            false,
+            load,
+        );
+        assert_eq!(caps.colour_depth, ColourDepth::TrueColour);
+    }
+
+    #[test]
+    fn terminfo_colour_depth() {
+        for (colours, depth) in [
+            (-1, ColourDepth::Mono),
+            (2, ColourDepth::Mono),
+            (8, ColourDepth::C8),
+            (16, ColourDepth::C16),
+            (88, ColourDepth::C16),
+            (256, ColourDepth::C256),
+            (0x1000000, ColourDepth::TrueColour),
+        ] {
+            assert_eq!(caps(&entry(colours)).colour_depth, depth);
+        }
+
+        /*
+         * The extensions used to advertise direct colour.
+         */
+        for name in ["RGB", "Tc"] {
+            let e = Entry { ext_bools: vec![(name, true)], ..entry(256) };
+            assert_eq!(caps(&e).colour_depth, ColourDepth::TrueColour);
+        }
+    }
+
+    #[test]
+    fn terminfo_line_glitch() {
+        assert!(caps(&entry(8)).line_glitch);
+
+        let mut e = entry(8);
+        e.bools[BOOL_EAT_NEWLINE_GLITCH] = false;
+        assert!(!caps(&e).line_glitch);
+
+        e.bools[BOOL_AUTO_RIGHT_MARGIN] = false;
+        assert!(caps(&e).line_glitch);
+    }
+
+    #[test]
+    fn terminfo_strings() {
+        let e = entry(8);
+        assert!(!caps(&e).italics);
+        assert!(!caps(&e).alternate_screen);
+
+        let mut strings = vec![None; STR_ENTER_ITALICS_MODE + 1];
+        strings[STR_ENTER_CA_MODE] = Some(&b"\x1b[?1049h"[..]);
+        strings[STR_ENTER_ITALICS_MODE] = Some(&b"\x1b[3m"[..]);
+        let e = Entry { strings, ..entry(8) };
+        assert!(caps(&e).italics);
+        assert!(!caps(&e).alternate_screen);
+
+        let mut e = e;
+        e.strings[STR_EXIT_CA_MODE] = Some(&b"\x1b[?1049l"[..]);
+        assert!(caps(&e).alternate_screen);
+    }
+
+    #[test]
+    fn terminfo_runs() {
+        let none = Capabilities::default();
+        assert!(!none.erase_chars && !none.repeat_char);
+        assert!(!none.back_colour_erase);
+
+        let caps = caps(&entry(8));
+        assert!(!caps.erase_chars && !caps.repeat_char);
+        assert!(!caps.back_colour_erase);
+
+        let mut e = entry(8);
+        e.bools[BOOL_BACK_COLOR_ERASE] = true;
+        e.strings = vec![None; STR_REPEAT_CHAR + 1];
+        e.strings[STR_ERASE_CHARS] = Some(&b"\x1b[%p1%dX"[..]);
+        let caps = Capabilities::from_terminfo(&e.encode()).unwrap();
+        assert!(caps.erase_chars && !caps.repeat_char);
+        assert!(caps.back_colour_erase);
+
+        e.strings[STR_REPEAT_CHAR] = Some(&b"%p1%c\x1b[%p2%{1}%-%db"[..]);
+        let caps = Capabilities::from_terminfo(&e.encode()).unwrap();
+        assert!(caps.repeat_char);
+    }
+
+    #[test]
+    fn terminfo_row_address() {
+        assert!(!Capabilities::default().row_address);
+        assert!(!caps(&entry(8)).row_address);
+
+        let mut strings = vec![None; STR_ROW_ADDRESS + 1];
+        strings[STR_ROW_ADDRESS] = Some(&b"\x1b[%i%p1%dd"[..]);
+        assert!(caps(&Entry { strings, ..entry(8) }).row_address);
+    }
+
+    #[test]
+    fn terminfo_scroll_region() {
+        let all = [
+            STR_CHANGE_SCROLL_REGION,
+            STR_PARM_INDEX,
+            STR_PARM_RINDEX,
+            STR_PARM_INSERT_LINE,
+            STR_PARM_DELETE_LINE,
+        ];
+        assert!(!Capabilities::default().scroll_region);
+        assert!(!caps(&entry(8)).scroll_region);
+
+        for missing in all {
+            let mut strings = vec![None; STR_PARM_RINDEX + 1];
+            for idx in all.iter().filter(|&&idx| idx != missing) {
+                strings[*idx] = Some(&b"x"[..]);
+            }
+            let mut e = Entry { strings, ..entry(8) };
+            assert!(!caps(&e).scroll_region);
+
+            e.strings[missing] = Some(&b"x"[..]);
+            assert!(caps(&e).scroll_region);
+        }
+    }
+}
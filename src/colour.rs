@@ -0,0 +1,283 @@
+use super::region::{Colour, Format};
+
+/**
+ * The range of colours that a terminal is able to display.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ColourDepth {
+    Mono,
+    C8,
+    C16,
+    C256,
+    TrueColour,
+}
+
+/*
+ * The default xterm values for the 16 standard colours.  Terminals differ
+ * here, and users often reconfigure them, but this is a reasonable guess.
+ */
+const PALETTE16: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xCD, 0x00, 0x00),
+    (0x00, 0xCD, 0x00),
+    (0xCD, 0xCD, 0x00),
+    (0x00, 0x00, 0xEE),
+    (0xCD, 0x00, 0xCD),
+    (0x00, 0xCD, 0xCD),
+    (0xE5, 0xE5, 0xE5),
+    (0x7F, 0x7F, 0x7F),
+    (0xFF, 0x00, 0x00),
+    (0x00, 0xFF, 0x00),
+    (0xFF, 0xFF, 0x00),
+    (0x5C, 0x5C, 0xFF),
+    (0xFF, 0x00, 0xFF),
+    (0x00, 0xFF, 0xFF),
+    (0xFF, 0xFF, 0xFF),
+];
+
+/*
+ * The intensity of each step along an axis of the 6x6x6 colour cube that
+ * occupies indexes 16 through 231 of the 256 colour palette.
+ */
+const CUBE: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+
+/**
+ * Determine the RGB value of an entry in the 256 colour palette.
+ */
+pub(crate) fn rgb256(idx: u8) -> (u8, u8, u8) {
+    match idx {
+        0..=15 => PALETTE16[idx as usize],
+        16..=231 => {
+            let i = idx - 16;
+            (
+                CUBE[(i / 36) as usize],
+                CUBE[(i / 6 % 6) as usize],
+                CUBE[(i % 6) as usize],
+            )
+        }
+        232..=255 => {
+            let v = 8 + (idx - 232) * 10;
+            (v, v, v)
+        }
+    }
+}
+
+/**
+ * An approximation of the perceived difference between two colours, using the
+ * "redmean" weighting of the RGB components.  This is much cheaper than a
+ * conversion to a perceptually uniform colour space and is good enough for
+ * choosing among palette entries.
+ */
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let rmean = (a.0 as i32 + b.0 as i32) / 2;
+    let dr = a.0 as i32 - b.0 as i32;
+    let dg = a.1 as i32 - b.1 as i32;
+    let db = a.2 as i32 - b.2 as i32;
+
+    ((((512 + rmean) * dr * dr) >> 8)
+        + 4 * dg * dg
+        + (((767 - rmean) * db * db) >> 8)) as u32
+}
+
+/**
+ * Find the closest of the first "count" entries in the 16 colour palette.
+ */
+fn nearest16(rgb: (u8, u8, u8), count: usize) -> u8 {
+    (0..count).min_by_key(|&i| distance(rgb, PALETTE16[i])).unwrap() as u8
+}
+
+/**
+ * Find the closest entry in the 256 colour palette.  The first 16 entries are
+ * often reconfigured by the user, so we consider only the colour cube and the
+ * grey ramp.
+ */
+fn nearest256(rgb: (u8, u8, u8)) -> u8 {
+    let axis = |v: u8| {
+        (0..CUBE.len())
+            .min_by_key(|&i| (CUBE[i] as i32 - v as i32).abs())
+            .unwrap() as u8
+    };
+    let cube = 16 + 36 * axis(rgb.0) + 6 * axis(rgb.1) + axis(rgb.2);
+
+    let avg = ((rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3) as u8;
+    let grey = 232 + (avg.saturating_sub(3) / 10).min(23);
+
+    if distance(rgb, rgb256(grey)) < distance(rgb, rgb256(cube)) {
+        grey
+    } else {
+        cube
+    }
+}
+
+/**
+ * Map a colour onto the nearest colour that can be displayed at the given
+ * depth.
+ */
+fn downgrade(c: Colour, depth: ColourDepth) -> Colour {
+    match (c, depth) {
+        (Colour::Default | Colour::UseExisting, _) => c,
+        (_, ColourDepth::TrueColour) => c,
+        (_, ColourDepth::Mono) => Colour::Default,
+
+        (Colour::RGB(r, g, b), ColourDepth::C256) => {
+            Colour::C256(nearest256((r, g, b)))
+        }
+        (Colour::RGB(r, g, b), ColourDepth::C16) => {
+            Colour::C16(nearest16((r, g, b), 16))
+        }
+        (Colour::RGB(r, g, b), ColourDepth::C8) => {
+            Colour::C16(nearest16((r, g, b), 8))
+        }
+
+        (Colour::C256(_), ColourDepth::C256) => c,
+        (Colour::C256(idx), ColourDepth::C16) if idx < 16 => Colour::C16(idx),
+        (Colour::C256(idx), ColourDepth::C16) => {
+            Colour::C16(nearest16(rgb256(idx), 16))
+        }
+        (Colour::C256(idx), ColourDepth::C8) => {
+            Colour::C16(nearest16(rgb256(idx), 8))
+        }
+
+        /*
+         * Fold the bright colours onto their regular counterparts.
+         */
+        (Colour::C16(idx @ 8..=15), ColourDepth::C8) => Colour::C16(idx - 8),
+        (Colour::C16(_), _) => c,
+    }
+}
+
+/**
+ * Map the colours in a format onto those that can be displayed at the given
+ * depth.  Underline colours require at least 256 colour support, and are
+ * otherwise dropped.
+ */
+pub(crate) fn downgrade_format(f: &Format, depth: ColourDepth) -> Format {
+    if depth == ColourDepth::TrueColour {
+        return *f;
+    }
+
+    Format {
+        fg: downgrade(f.fg, depth),
+        bg: downgrade(f.bg, depth),
+        underline_colour: if depth >= ColourDepth::C256 {
+            downgrade(f.underline_colour, depth)
+        } else {
+            Colour::Default
+        },
+        ..*f
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest16_known_colours() {
+        assert_eq!(nearest16((0xFF, 0x00, 0x00), 16), 9);
+        assert_eq!(nearest16((0xC0, 0x10, 0x10), 16), 1);
+        assert_eq!(nearest16((0x10, 0x10, 0xE0), 16), 4);
+        assert_eq!(nearest16((0x80, 0x80, 0x80), 16), 8);
+        assert_eq!(nearest16((0xFF, 0xFF, 0xFF), 16), 15);
+
+        /*
+         * Without the bright colours, we fall back on the regular ones.
+         */
+        assert_eq!(nearest16((0xFF, 0x00, 0x00), 8), 1);
+        assert_eq!(nearest16((0xFF, 0xFF, 0xFF), 8), 7);
+    }
+
+    #[test]
+    fn nearest256_cube_entries() {
+        for idx in 16..=231 {
+            assert_eq!(nearest256(rgb256(idx)), idx);
+        }
+        assert_eq!(nearest256((0x5F, 0x87, 0xAF)), 67);
+        assert_eq!(nearest256((0xFA, 0x05, 0x02)), 196);
+    }
+
+    #[test]
+    fn nearest256_greys() {
+        for idx in 232..=255 {
+            assert_eq!(nearest256(rgb256(idx)), idx);
+        }
+        assert_eq!(nearest256((0x80, 0x80, 0x80)), 244);
+        assert_eq!(nearest256((0x81, 0x7F, 0x80)), 244);
+        assert_eq!(nearest256((0x08, 0x08, 0x08)), 232);
+        assert_eq!(nearest256((0xEE, 0xEE, 0xEE)), 255);
+
+        /*
+         * Greys that coincide with the cube stay in the cube.
+         */
+        assert_eq!(nearest256((0x00, 0x00, 0x00)), 16);
+        assert_eq!(nearest256((0xFF, 0xFF, 0xFF)), 231);
+    }
+
+    #[test]
+    fn downgrade_each_depth() {
+        let rgb = Colour::RGB(0xFA, 0x05, 0x02);
+        assert_eq!(downgrade(rgb, ColourDepth::TrueColour), rgb);
+        assert_eq!(downgrade(rgb, ColourDepth::C256), Colour::C256(196));
+        assert_eq!(downgrade(rgb, ColourDepth::C16), Colour::C16(9));
+        assert_eq!(downgrade(rgb, ColourDepth::C8), Colour::C16(1));
+        assert_eq!(downgrade(rgb, ColourDepth::Mono), Colour::Default);
+
+        let c256 = Colour::C256(250);
+        assert_eq!(downgrade(c256, ColourDepth::C256), c256);
+        assert_eq!(downgrade(c256, ColourDepth::C16), Colour::C16(7));
+        assert_eq!(downgrade(c256, ColourDepth::C8), Colour::C16(7));
+        assert_eq!(
+            downgrade(Colour::C256(3), ColourDepth::C16),
+            Colour::C16(3)
+        );
+
+        let c16 = Colour::C16(12);
+        assert_eq!(downgrade(c16, ColourDepth::C256), c16);
+        assert_eq!(downgrade(c16, ColourDepth::C16), c16);
+        assert_eq!(downgrade(c16, ColourDepth::C8), Colour::C16(4));
+        assert_eq!(downgrade(c16, ColourDepth::Mono), Colour::Default);
+
+        for depth in [ColourDepth::Mono, ColourDepth::C8, ColourDepth::C256] {
+            assert_eq!(downgrade(Colour::Default, depth), Colour::Default);
+            assert_eq!(
+                downgrade(Colour::UseExisting, depth),
+                Colour::UseExisting
+            );
+        }
+    }
+
+    fn colours(f: Format) -> (Colour, Colour, Colour) {
+        (f.fg, f.bg, f.underline_colour)
+    }
+
+    #[test]
+    fn downgrade_format_each_depth() {
+        let f = Format {
+            bold: true,
+            fg: Colour::RGB(0xFA, 0x05, 0x02),
+            bg: Colour::C16(12),
+            underline_colour: Colour::RGB(0x5F, 0x87, 0xAF),
+            ..Format::default()
+        };
+
+        assert!(downgrade_format(&f, ColourDepth::TrueColour) == f);
+        assert!(downgrade_format(&f, ColourDepth::Mono).bold);
+
+        assert_eq!(
+            colours(downgrade_format(&f, ColourDepth::C256)),
+            (Colour::C256(196), Colour::C16(12), Colour::C256(67))
+        );
+        assert_eq!(
+            colours(downgrade_format(&f, ColourDepth::C16)),
+            (Colour::C16(9), Colour::C16(12), Colour::Default)
+        );
+        assert_eq!(
+            colours(downgrade_format(&f, ColourDepth::C8)),
+            (Colour::C16(1), Colour::C16(4), Colour::Default)
+        );
+        assert_eq!(
+            colours(downgrade_format(&f, ColourDepth::Mono)),
+            (Colour::Default, Colour::Default, Colour::Default)
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use super::region::*;
+
+/**
+ * Identifies a layer within a Compositor.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LayerId(usize);
+
+struct Layer {
+    id: LayerId,
+    region: Region,
+    x: usize,
+    y: usize,
+    z: i32,
+    visible: bool,
+    shadow: bool,
+}
+
+/**
+ * A stack of regions (e.g., dialogs, tooltips, and notifications) to be drawn
+ * over one another and flattened into a single Region before it is passed to
+ * Draw::apply().  Layers with a higher z value are drawn on top; layers with
+ * the same z value are drawn in the order in which they were added.
+ *
+ * Transparent cells in a layer (see Region::new_transparent()) let whatever is
+ * beneath show through, as do Colour::UseExisting colours in the format of a
+ * cell.
+ */
+pub struct Compositor {
+    layers: Vec<Layer>,
+    next_id: usize,
+    shadow: Format,
+}
+
+impl Default for Compositor {
+    fn default() -> Self {
+        Compositor::new()
+    }
+}
+
+impl Compositor {
+    pub fn new() -> Compositor {
+        Compositor {
+            layers: Vec::new(),
+            next_id: 0,
+            shadow: Format {
+                dim: true,
+                fg: Colour::C16(8),
+                bg: Colour::C16(0),
+                ..Default::default()
+            },
+        }
+    }
+
+    /**
+     * Add a layer with its top-left corner at (x, y) in the target region.
+     */
+    pub fn add(
+        &mut self,
+        region: Region,
+        x: usize,
+        y: usize,
+        z: i32,
+    ) -> LayerId {
+        let id = LayerId(self.next_id);
+        self.next_id += 1;
+
+        self.layers.push(Layer {
+            id,
+            region,
+            x,
+            y,
+            z,
+            visible: true,
+            shadow: false,
+        });
+        id
+    }
+
+    /**
+     * Remove a layer, handing back its region.
+     */
+    pub fn remove(&mut self, id: LayerId) -> Option<Region> {
+        let idx = self.layers.iter().position(|l| l.id == id)?;
+        Some(self.layers.remove(idx).region)
+    }
+
+    fn layer(&self, id: LayerId) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.id == id)
+    }
+
+    fn layer_mut(&mut self, id: LayerId) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|l| l.id == id)
+    }
+
+    pub fn region(&self, id: LayerId) -> Option<&Region> {
+        self.layer(id).map(|l| &l.region)
+    }
+
+    pub fn region_mut(&mut self, id: LayerId) -> Option<&mut Region> {
+        self.layer_mut(id).map(|l| &mut l.region)
+    }
+
+    pub fn position(&self, id: LayerId) -> Option<Point> {
+        self.layer(id).map(|l| Point::new(l.x, l.y))
+    }
+
+    pub fn set_position(&mut self, id: LayerId, x: usize, y: usize) {
+        if let Some(l) = self.layer_mut(id) {
+            l.x = x;
+            l.y = y;
+        }
+    }
+
+    pub fn z(&self, id: LayerId) -> Option<i32> {
+        self.layer(id).map(|l| l.z)
+    }
+
+    pub fn set_z(&mut self, id: LayerId, z: i32) {
+        if let Some(l) = self.layer_mut(id) {
+            l.z = z;
+        }
+    }
+
+    pub fn visible(&self, id: LayerId) -> bool {
+        self.layer(id).map(|l| l.visible).unwrap_or(false)
+    }
+
+    pub fn set_visible(&mut self, id: LayerId, visible: bool) {
+        if let Some(l) = self.layer_mut(id) {
+            l.visible = visible;
+        }
+    }
+
+    /**
+     * Enable or disable a drop shadow, offset one row down and two columns to
+     * the right of the layer, in the manner of a classic text mode dialog.
+     */
+    pub fn set_shadow(&mut self, id: LayerId, shadow: bool) {
+        if let Some(l) = self.layer_mut(id) {
+            l.shadow = shadow;
+        }
+    }
+
+    /**
+     * Set the format applied to cells that fall beneath a drop shadow.  The
+     * contents of those cells are left as they are, and any colour in the
+     * format that is Colour::UseExisting is not changed.
+     */
+    pub fn set_shadow_format(&mut self, f: Format) {
+        self.shadow = f;
+    }
+
+    /**
+     * Draw each visible layer, from the bottom of the stack to the top, over
+     * the existing contents of the target region.  The cursor of the topmost
+     * visible layer that has one becomes the cursor of the target.
+     */
+    pub fn compose(&self, target: &mut Region) {
+        let mut order: Vec<&Layer> =
+            self.layers.iter().filter(|l| l.visible).collect();
+        order.sort_by_key(|l| l.z);
+
+        for l in order.iter() {
+            let (w, h) = (l.region.width(), l.region.height());
+
+            if l.shadow && w > 0 && h > 0 {
+                let right = Rect::new(l.x + w, l.y + 1, 2, h);
+                let below = Rect::new(l.x + 2, l.y + h, w, 1);
+                target.paint_rect(right, &self.shadow);
+                target.paint_rect(below, &self.shadow);
+            }
+
+            target.composite(&l.region, Rect::new(0, 0, w, h), l.x, l.y);
+        }
+
+        let top = order.iter().rev().find_map(|l| {
+            l.region.cursor().map(|p| {
+                (Point::new(l.x + p.x, l.y + p.y), l.region.cursor_style())
+            })
+        });
+        if let Some((p, style)) = top {
+            if p.x < target.width() && p.y < target.height() {
+                target.set_cursor(Some(p));
+                target.set_cursor_style(style);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(r: &Region, y: usize) -> String {
+        (0..r.width()).map(|x| r.cell(x, y).unwrap().contents()).collect()
+    }
+
+    fn layer(s: &str) -> Region {
+        let mut r = Region::new(s.len(), 1);
+        r.str(0, 0, s);
+        r
+    }
+
+    #[test]
+    fn z_order() {
+        let mut c = Compositor::new();
+        let a = c.add(layer("aaaa"), 0, 0, 1);
+        c.add(layer("bb"), 1, 0, 0);
+        c.add(layer("c"), 2, 0, 1);
+
+        /*
+         * Higher layers are drawn later, and so are later layers at the same
+         * level.
+         */
+        let mut target = Region::new(5, 1);
+        c.compose(&mut target);
+        assert_eq!(row(&target, 0), "aaca ");
+
+        c.set_z(a, -1);
+        c.compose(&mut target);
+        assert_eq!(row(&target, 0), "abca ");
+
+        c.set_visible(a, false);
+        let mut target = Region::new(5, 1);
+        c.compose(&mut target);
+        assert_eq!(row(&target, 0), " bc  ");
+
+        assert!(c.remove(a).is_some());
+        assert!(c.remove(a).is_none());
+        assert!(!c.visible(a));
+    }
+
+    #[test]
+    fn shadows() {
+        let mut c = Compositor::new();
+        let top = c.add(layer("ab"), 1, 0, 1);
+        c.set_shadow(top, true);
+
+        let mut target = Region::new(5, 3);
+        for y in 0..3 {
+            target.str(0, y, "....");
+        }
+        c.compose(&mut target);
+
+        /*
+         * The shadow leaves contents as they are, and changes only the
+         * format of the cells to the right and below.
+         */
+        assert_eq!(row(&target, 0), ".ab. ");
+        assert_eq!(row(&target, 1), ".... ");
+        let shaded = |x, y| target.cell(x, y).unwrap().format().dim;
+        assert!(!shaded(0, 1) && !shaded(1, 1) && !shaded(2, 1));
+        assert!(shaded(3, 1) && shaded(4, 1));
+        assert!(!shaded(3, 0) && !shaded(1, 0));
+        assert!(!shaded(3, 2));
+    }
+
+    #[test]
+    fn shadows_and_z_order() {
+        let mut c = Compositor::new();
+        let low = c.add(layer("xx"), 0, 0, 0);
+        let high = c.add(layer("yyyy"), 0, 1, 1);
+        c.set_shadow(low, true);
+        c.set_shadow(high, true);
+        c.set_shadow_format(Format { bold: true, ..Default::default() });
+
+        /*
+         * The shadow of a lower layer is covered by the layers above it,
+         * while the shadow of a higher layer falls on the lower one.  Shadows
+         * that would fall beyond the target are clipped.
+         */
+        let mut target = Region::new(4, 2);
+        c.compose(&mut target);
+        assert_eq!(row(&target, 1), "yyyy");
+        assert!(!(0..4).any(|x| target.cell(x, 1).unwrap().format().bold));
+
+        c.set_z(high, -1);
+        c.set_position(high, 1, 0);
+        let mut target = Region::new(6, 2);
+        c.compose(&mut target);
+        assert_eq!(row(&target, 0), "xxyyy ");
+        let bold: Vec<bool> =
+            (0..6).map(|x| target.cell(x, 1).unwrap().format().bold).collect();
+        assert_eq!(bold, [false, false, true, true, true, true]);
+        assert_eq!(c.position(high), Some(Point::new(1, 0)));
+    }
+
+    #[test]
+    fn cursor_from_topmost_layer() {
+        let mut c = Compositor::new();
+        let mut a = layer("aa");
+        a.set_cursor(Some(Point::new(1, 0)));
+        let mut b = layer("bb");
+        b.set_cursor(Some(Point::new(0, 0)));
+        let bar = CursorStyle { shape: CursorShape::Bar, ..Default::default() };
+        b.set_cursor_style(bar);
+        c.add(a, 0, 0, 1);
+        let b = c.add(b, 2, 0, 0);
+
+        let mut target = Region::new(4, 1);
+        c.compose(&mut target);
+        assert_eq!(target.cursor(), Some(Point::new(1, 0)));
+
+        c.set_z(b, 2);
+        c.compose(&mut target);
+        assert_eq!(target.cursor(), Some(Point::new(2, 0)));
+        assert_eq!(target.cursor_style(), bar);
+    }
+}
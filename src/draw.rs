@@ -1,28 +1,124 @@
+use super::caps::Capabilities;
+use super::colour::{downgrade_format, rgb256, ColourDepth};
+use super::motion::move_cursor;
 use super::region::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 
 pub struct Draw {
     preamble: Vec<String>,
     screen: Region,
+    invalid: Vec<bool>,
+    /**
+     * The stamp of each row of the region that was last drawn, if the screen
+     * is known to match it.
+     */
+    stamps: Vec<RowStamp>,
+    /**
+     * A hash of each row of the screen, for finding rows that have moved.
+     * Hashing is relatively expensive, so this is only done when needed and
+     * then kept until the row changes.
+     */
+    hashes: Vec<Option<u64>>,
     line_glitch: bool,
+    colour_depth: ColourDepth,
+    italics: bool,
+    scroll_region: bool,
+    erase_chars: bool,
+    repeat_char: bool,
+    back_colour_erase: bool,
+    row_address: bool,
+    synchronized_output: bool,
+    cursor_visible: bool,
+    cursor_at: Option<Point>,
+    cursor_style: CursorStyle,
+    buf: Vec<u8>,
 }
 
 impl Draw {
     pub fn new(width: usize, height: usize) -> Draw {
         Draw {
             screen: Region::new(width, height),
+            invalid: vec![false; width * height],
+            stamps: vec![RowStamp::default(); height],
+            hashes: vec![None; height],
             /*
              * For the first frame, clear the whole screen and disable the
              * cursor to match the contents of the initial cached screen.
              */
             preamble: vec!["\x1b[H\x1b[2J\x1b[?25l".into()],
             line_glitch: true,
+            colour_depth: ColourDepth::TrueColour,
+            italics: true,
+            scroll_region: false,
+            erase_chars: false,
+            repeat_char: false,
+            back_colour_erase: false,
+            row_address: false,
+            synchronized_output: false,
+            cursor_visible: false,
+            cursor_at: None,
+            cursor_style: CursorStyle::default(),
+            buf: Vec::new(),
         }
     }
 
+    /**
+     * Create a Draw that is configured for the features of a particular
+     * terminal; e.g., as detected by Capabilities::from_env().
+     */
+    pub fn with_capabilities(
+        width: usize,
+        height: usize,
+        caps: &Capabilities,
+    ) -> Draw {
+        let mut d = Draw::new(width, height);
+        d.set_line_glitch(caps.line_glitch);
+        d.set_colour_depth(caps.colour_depth);
+        d.italics = caps.italics;
+        d.set_scroll_region(caps.scroll_region);
+        d.erase_chars = caps.erase_chars;
+        d.repeat_char = caps.repeat_char;
+        d.back_colour_erase = caps.back_colour_erase;
+        d.row_address = caps.row_address;
+        d.set_synchronized_output(caps.synchronized_output);
+        d
+    }
+
     pub fn set_line_glitch(&mut self, line_glitch: bool) {
         self.line_glitch = line_glitch;
     }
 
+    /**
+     * Set the range of colours supported by the terminal.  Colours in the
+     * region that cannot be displayed are replaced with the closest available
+     * colour as the frame is rendered.
+     */
+    pub fn set_colour_depth(&mut self, depth: ColourDepth) {
+        self.colour_depth = depth;
+    }
+
+    /**
+     * Allow the use of scrolling regions and line insertion and deletion to
+     * move blocks of rows that have shifted up or down between frames.  This
+     * is off by default, as not every terminal supports these sequences.
+     */
+    pub fn set_scroll_region(&mut self, scroll_region: bool) {
+        self.scroll_region = scroll_region;
+    }
+
+    /**
+     * Wrap the output for each frame in the sequences that begin and end a
+     * synchronized update (mode 2026), so that the terminal displays the
+     * frame all at once rather than as it arrives.  Terminals that do not
+     * recognise the mode ought to ignore the sequences, but not all do, so
+     * this is best enabled only where support has been detected.
+     */
+    pub fn set_synchronized_output(&mut self, synchronized_output: bool) {
+        self.synchronized_output = synchronized_output;
+    }
+
     pub fn height(&self) -> usize {
         self.screen.height()
     }
@@ -31,38 +127,450 @@ impl Draw {
         self.screen.width()
     }
 
+    /**
+     * Change the size of the screen.  The terminal will generally have
+     * rearranged or discarded what was on the screen, so our cached copy can
+     * no longer be trusted: the next frame will clear the screen and then
+     * draw everything.
+     */
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.screen.resize(width, height);
+        self.invalid = vec![false; width * height];
+        self.stamps = vec![RowStamp::default(); height];
+        self.hashes = vec![None; height];
+        self.invalidate();
+    }
+
+    /**
+     * Discard our record of what is on the screen; e.g., because some other
+     * process has written to the terminal.  The next frame will clear the
+     * screen and then draw everything.
+     */
+    pub fn invalidate(&mut self) {
+        self.screen.clear();
+        self.invalid.fill(false);
+        self.stamps.fill(RowStamp::default());
+        self.hashes.fill(None);
+        self.preamble.push("\x1b[0m\x1b[H\x1b[2J".into());
+    }
+
+    /**
+     * Discard our record of what is on part of the screen.  Every cell in the
+     * rectangle will be drawn again in the next frame, whether or not it has
+     * changed.
+     */
+    pub fn invalidate_rect(&mut self, rect: Rect) {
+        let width = self.width();
+        let rect = rect.clip(width, self.height());
+
+        for y in rect.y..(rect.y + rect.height) {
+            self.invalid[y * width + rect.x..y * width + rect.x + rect.width]
+                .fill(true);
+            self.stamps[y] = RowStamp::default();
+        }
+    }
+
     pub fn cleanup(self) -> String {
+        /*
+         * Put back the default cursor shape and colour if we changed them.
+         */
+        let mut out = Vec::new();
+        write_cursor_style(
+            &mut out,
+            &self.cursor_style,
+            &CursorStyle::default(),
+        )
+        .expect("writing to a Vec cannot fail");
+        let restore = String::from_utf8(out).expect("output is always UTF-8");
+
         /*
          * Move the cursor to the bottom left of the screen and turn it back on,
          * so that the shell prompt ends up in the right place.
          */
-        format!("\x1b[{};{}f\x1b[?25h", self.height(), 1)
+        format!("{}\x1b[{};{}f\x1b[?25h", restore, self.height(), 1)
     }
 
     pub fn preamble(&mut self, s: &str) {
         self.preamble.push(s.to_string());
     }
 
+    /**
+     * Render the changes required to bring the terminal up to date with the
+     * contents of this region, returning the escape sequences as a string.
+     * This is a convenience wrapper around apply_to().
+     */
     pub fn apply(&mut self, r: &Region) -> String {
+        let mut out = Vec::new();
+        self.apply_to(r, &mut out).expect("writing to a Vec cannot fail");
+        String::from_utf8(out).expect("output is always UTF-8")
+    }
+
+    /**
+     * Render the changes required to bring the terminal up to date with the
+     * contents of this region, writing the escape sequences directly to the
+     * provided output.  The frame is assembled in a buffer that is reused
+     * between calls and then passed to the output in a single write.  When
+     * the same region is drawn again, rows that have not been changed in the
+     * meantime are skipped without being compared.
+     */
+    pub fn apply_to<W: Write>(
+        &mut self,
+        r: &Region,
+        w: &mut W,
+    ) -> io::Result<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+
+        /*
+         * The sequence that begins a synchronized update is written first, and
+         * taken back again if the frame turns out to be empty.
+         */
+        let begin: &[u8] = b"\x1b[?2026h";
+        if self.synchronized_output {
+            buf.extend_from_slice(begin);
+        }
+        self.render(r, &mut buf)?;
+        if self.synchronized_output {
+            if buf.len() == begin.len() {
+                buf.clear();
+            } else {
+                buf.extend_from_slice(b"\x1b[?2026l");
+            }
+        }
+        let res = w.write_all(&buf);
+
+        self.buf = buf;
+        res
+    }
+
+    /**
+     * Look for a block of rows that has moved up or down since the last frame
+     * (e.g., in a scrolling log view) and, if it is worth doing, have the
+     * terminal move those rows rather than drawing them all again.  The cached
+     * screen is updated to match, so that the rows that still differ are
+     * then drawn as usual.
+     */
+    fn scroll(
+        &mut self,
+        r: &Region,
+        out: &mut Vec<u8>,
+        last_format: &mut Option<Format>,
+    ) -> io::Result<()> {
+        let height = self.screen.height();
+        let width = self.screen.width();
+
+        if !self.scroll_region
+            || height < 2
+            || r.width() != width
+            || r.height() != height
+            || (0..height).any(|y| self.row_invalid(y))
+        {
+            return Ok(());
+        }
+
+        let differs: Vec<bool> = (0..height)
+            .map(|y| {
+                self.stamps[y] != r.row_stamp(y)
+                    && self.screen.row(y) != r.row(y)
+            })
+            .collect();
+        let Some(top) = (0..height).find(|&y| differs[y]) else {
+            return Ok(());
+        };
+        let bottom = (0..height).rev().find(|&y| differs[y]).unwrap();
+
+        /*
+         * Only the rows within the block can take part in the shift.  A row
+         * of the region with an unchanged stamp matches the screen, so need
+         * not be hashed itself.
+         */
+        let mut old = vec![0; height];
+        let mut new = vec![0; height];
+        for y in top..=bottom {
+            let screen = &self.screen;
+            old[y] =
+                *self.hashes[y].get_or_insert_with(|| row_hash(screen.row(y)));
+            new[y] = if self.stamps[y] == r.row_stamp(y) {
+                old[y]
+            } else {
+                row_hash(r.row(y))
+            };
+        }
+        let blank = row_hash(
+            &std::iter::repeat_with(Cell::default)
+                .take(width)
+                .collect::<Vec<_>>(),
+        );
+        let before = (top..=bottom).filter(|&y| old[y] == new[y]).count();
+
+        /*
+         * Find the shift that leaves the most rows in the block matching the
+         * new frame.  A row that is uncovered by the shift will be blank.
+         */
+        let mut best = None;
+        for n in 1..=(bottom - top) {
+            for up in [true, false] {
+                let after = (top..=bottom)
+                    .filter(|&y| {
+                        let moved = if up {
+                            (y + n <= bottom).then(|| old[y + n])
+                        } else {
+                            (y >= top + n).then(|| old[y - n])
+                        };
+                        new[y] == moved.unwrap_or(blank)
+                    })
+                    .count();
+
+                let gain = after.saturating_sub(before);
+                if gain > best.map(|(g, _, _)| g).unwrap_or(0) {
+                    best = Some((gain, n, up));
+                }
+            }
+        }
+        let Some((_, n, up)) = best else {
+            return Ok(());
+        };
+
+        /*
+         * Rows that differ only slightly may be cheaper to patch in place than
+         * to scroll, so compare the number of cells that would need to be
+         * drawn either way.
+         */
+        let def = Cell::default();
+        let changed = |a: &[Cell], y: usize| {
+            let b = r.row(y);
+            (0..width).filter(|&x| a.get(x).unwrap_or(&def) != &b[x]).count()
+        };
+        let mut cost_before = 0;
+        let mut cost_after = 0;
+        for y in top..=bottom {
+            let moved = if up {
+                (y + n <= bottom).then(|| self.screen.row(y + n))
+            } else {
+                (y >= top + n).then(|| self.screen.row(y - n))
+            };
+            cost_before += changed(self.screen.row(y), y);
+            cost_after += changed(moved.unwrap_or(&[]), y);
+        }
+
+        let mut seq = Vec::new();
+        write_scroll(&mut seq, top, bottom, height, n, up)?;
+        if cost_after + seq.len() >= cost_before {
+            return Ok(());
+        }
+
+        /*
+         * The rows that are uncovered by scrolling are filled with the
+         * current background colour, so make sure it is the default.
+         */
+        if last_format.as_ref() != Some(&Format::default()) {
+            write_format(out, last_format.as_ref(), &Format::default())?;
+            *last_format = Some(Format::default());
+        }
+        out.extend_from_slice(&seq);
+        self.screen.scroll(top, bottom, n, up);
+        self.stamps[top..=bottom].fill(RowStamp::default());
+        self.hashes[top..=bottom].fill(None);
+
+        Ok(())
+    }
+
+    /**
+     * Whether any cell in a row has been invalidated.  Such a row never has a
+     * stamp, so only those rows without one need to be checked.
+     */
+    fn row_invalid(&self, y: usize) -> bool {
+        let width = self.screen.width();
+        self.stamps[y] == RowStamp::default()
+            && self.invalid[y * width..(y + 1) * width].contains(&true)
+    }
+
+    /**
+     * Decide whether the cell at (x, y), which is about to be drawn in format
+     * "f", begins a run of identical cells that can be drawn more cheaply with
+     * a single erase or repeat sequence than one cell at a time.
+     */
+    fn run(
+        &self,
+        r: &Region,
+        x: usize,
+        y: usize,
+        nc: &Cell,
+        f: &Format,
+    ) -> Run {
         let height = self.screen.height();
         let width = self.screen.width();
+        let def = Cell::default();
+
+        let mut chars = nc.contents().chars();
+        if nc.width() != 1 || chars.next().is_none() || chars.next().is_some() {
+            return Run::Single;
+        }
+
+        let new = |cx: usize| r.cell(cx, y).unwrap_or(&def);
+        let changed = |cx: usize| {
+            self.invalid[y * width + cx]
+                || self.screen.cell(cx, y) != Some(new(cx))
+        };
+
+        /*
+         * Erasing leaves blank cells in the current background colour, if the
+         * terminal supports that, and with no other attributes.
+         */
+        let erasable = nc.contents() == " "
+            && *f == Format { bg: f.bg, ..Default::default() }
+            && (f.bg == Colour::Default || self.back_colour_erase);
+
+        if erasable
+            && (x..width).all(|cx| new(cx) == nc)
+            && (x..width).filter(|&cx| changed(cx)).count() > 3
+        {
+            return Run::EraseLine;
+        }
+
+        /*
+         * Count only the cells that actually need to be drawn, so that a run
+         * is never more expensive than drawing those cells individually.  If
+         * we cannot draw in the bottom-right cell, the run must stop short of
+         * it.
+         */
+        let limit = if !self.line_glitch && y == height - 1 {
+            width - 1
+        } else {
+            width
+        };
+        let n =
+            (x..limit).take_while(|&cx| new(cx) == nc && changed(cx)).count();
+        if n < 2 {
+            return Run::Single;
+        }
+
+        let plain = n * nc.contents().len();
+        let erase = (erasable && self.erase_chars).then(|| {
+            /*
+             * Allow for moving the cursor past the erased cells afterward.
+             */
+            format!("\x1b[{}X\x1b[{}C", n, n).len()
+        });
+        let repeat = self
+            .repeat_char
+            .then(|| nc.contents().len() + format!("\x1b[{}b", n - 1).len());
+
+        match (erase, repeat) {
+            (Some(e), Some(r)) if e <= r && e < plain => Run::Erase(n),
+            (Some(e), None) if e < plain => Run::Erase(n),
+            (_, Some(r)) if r < plain => Run::Repeat(n),
+            _ => Run::Single,
+        }
+    }
+
+    /**
+     * Update our record of the screen after a run of "n" identical cells has
+     * been drawn.
+     */
+    fn record_run(&mut self, x: usize, y: usize, n: usize, nc: &Cell) {
+        for cx in x..(x + n) {
+            self.screen.cell_mut(cx, y).unwrap().set_from(nc);
+        }
+    }
+
+    /**
+     * The format in which a cell is actually drawn, given the features of the
+     * terminal.
+     */
+    fn drawn_format(&self, c: &Cell) -> Format {
+        let mut f = downgrade_format(c.format(), self.colour_depth);
+        if !self.italics {
+            f.italic = false;
+        }
+        f
+    }
+
+    /**
+     * Where the cursor ends up after drawing up to column "x" of row "y".
+     * After the last column, a terminal with the VT100 line glitch waits to
+     * wrap until the next character arrives, which we record as a column
+     * just past the edge of the screen; any other terminal wraps at once.
+     */
+    fn after(&self, x: usize, y: usize) -> Point {
+        if x < self.screen.width() || self.line_glitch {
+            Point::new(x, y)
+        } else {
+            Point::new(0, y + 1)
+        }
+    }
+
+    /**
+     * If the cursor can be moved from one cell to another in the same row by
+     * drawing the cells in between again, return what would be written.  This
+     * is only possible if those cells are already on the screen in the format
+     * that is currently selected.  There is no point in going beyond a few
+     * cells, as a sequence that moves the cursor is never that long.
+     */
+    fn reemit(
+        &self,
+        from: Point,
+        to: Point,
+        format: Option<&Format>,
+    ) -> Option<Vec<u8>> {
+        let width = self.screen.width();
+        if from.y != to.y || from.x >= to.x || to.x > width {
+            return None;
+        }
+
+        let mut out = Vec::new();
+        let mut x = from.x;
+        while x < to.x {
+            let c = self.screen.cell(x, to.y)?;
+            if c.is_continuation()
+                || self.invalid[to.y * width + x]
+                || Some(&self.drawn_format(c)) != format
+            {
+                return None;
+            }
+
+            out.extend_from_slice(c.contents().as_bytes());
+            if out.len() > 6 {
+                return None;
+            }
+            x += c.width().max(1);
+        }
 
-        let mut out = String::new();
+        (x == to.x).then_some(out)
+    }
+
+    fn render(&mut self, r: &Region, out: &mut Vec<u8>) -> io::Result<()> {
+        let height = self.screen.height();
+        let width = self.screen.width();
+        let start = out.len();
 
         for preamble in self.preamble.drain(..) {
-            out.push_str(&preamble);
+            out.extend_from_slice(preamble.as_bytes());
         }
 
-        let refresh = false;
+        let mut last_format = None;
+        self.scroll(r, out, &mut last_format)?;
+
+        /*
+         * Anything written so far may have moved the cursor somewhere we do
+         * not know about.
+         */
+        let mut cursor = if out.len() == start { self.cursor_at } else { None };
+
         let mut contig = false;
         let mut redo = false;
         let def = Cell::default();
-
-        let mut last_format = None;
-        let mut last_row = None;
-        let mut last_col = None;
+        let stamped = r.width() == width && r.height() == height;
 
         for y in 0..height {
+            if stamped && self.stamps[y] == r.row_stamp(y) {
+                /*
+                 * Nothing in this row has changed since it was last drawn.
+                 */
+                contig = false;
+                continue;
+            }
+
             let mut x = 0;
 
             while x < width {
@@ -75,132 +583,995 @@ impl Draw {
                      */
                     break;
                 }
-                let oc = self.screen.cell_mut(x, y).unwrap();
+                let oc = self.screen.cell(x, y).unwrap();
                 let nc = if let Some(nc) = r.cell(x, y) { nc } else { &def };
 
+                let refresh = self.invalid[y * width + x];
                 if !redo && oc == nc && !refresh {
                     contig = false;
-                    x += oc.width();
+                    x += 1;
+                    continue;
+                }
+
+                if !contig
+                    && x > 0
+                    && (oc.is_continuation() || nc.is_continuation())
+                {
+                    /*
+                     * One half of a wide character has changed.  Terminals
+                     * generally erase the whole character when either half is
+                     * overwritten, so back up and redraw from the column to
+                     * the left.
+                     */
+                    redo = true;
+                    x -= 1;
                     continue;
                 }
                 redo = false;
 
-                if !contig {
+                let w = nc.width().max(1);
+                if !self.line_glitch && y == height - 1 && x + w >= width {
+                    /*
+                     * A wide character would reach the bottom-right cell; see
+                     * the comment above.
+                     */
+                    break;
+                }
+
+                let f = self.drawn_format(nc);
+                let run = self.run(r, x, y, nc, &f);
+
+                let erase = matches!(run, Run::EraseLine | Run::Erase(_));
+                if !contig || (erase && cursor != Some(Point::new(x, y))) {
                     /*
-                     * We did not write to the previous character in this row.
+                     * We did not write to the previous character in this row,
+                     * or the terminal is waiting to wrap after the last column
+                     * of the row above, which erasing will not do for us.
                      * Move the cursor into place.
                      */
-                    if Some(y) == last_row {
-                        if let Some(col) = last_col {
-                            let skip = x - col - 1;
-
-                            if skip == 1 {
-                                /*
-                                 * We would be skipping just one character.
-                                 * It is generally more efficient to backtrack
-                                 * and just emit that character.
-                                 */
-                                redo = true;
-                                contig = true;
-                                x -= 1;
-                                continue;
-                            }
-
-                            /*
-                             * It's just a jump to the right.
-                             */
-                            out += &format!("\x1b[{}C", skip);
-                        } else {
-                            /*
-                             * Use an absolute column address.
-                             */
-                            out += &format!("\x1b[{}G", x + 1);
-                        }
-                    } else {
-                        /*
-                         * Move directly to a specific cell.
-                         */
-                        out += &format!("\x1b[{};{}f", y + 1, x + 1);
-                    }
+                    let to = Point::new(x, y);
+                    let reemit = cursor.and_then(|from| {
+                        self.reemit(from, to, last_format.as_ref())
+                    });
+                    let vpa = self.row_address;
+                    move_cursor(out, cursor, to, width, vpa, reemit.as_deref());
                 }
 
-                if last_format.is_none() {
-                    last_format = Some(Format::default());
-                    out += &format!("\x1b[0m");
+                if last_format.as_ref() != Some(&f) {
+                    write_format(out, last_format.as_ref(), &f)?;
+                    last_format = Some(f);
                 }
 
-                if last_format.as_ref() != Some(nc.format()) {
-                    let mut attrs = vec![0];
-                    let f = nc.format();
+                match run {
+                    Run::Single => (),
+                    Run::EraseLine => {
+                        out.extend_from_slice(b"\x1b[K");
+                        self.record_run(x, y, width - x, nc);
 
-                    if f.bold {
-                        attrs.push(1);
+                        /*
+                         * Erasing does not move the cursor.
+                         */
+                        contig = false;
+                        cursor = Some(Point::new(x, y));
+                        x = width;
+                        continue;
                     }
+                    Run::Erase(n) => {
+                        write!(out, "\x1b[{}X", n)?;
+                        self.record_run(x, y, n, nc);
 
-                    if f.reverse {
-                        attrs.push(7);
+                        contig = false;
+                        cursor = Some(Point::new(x, y));
+                        x += n;
+                        continue;
                     }
+                    Run::Repeat(n) => {
+                        out.extend_from_slice(nc.contents().as_bytes());
+                        write!(out, "\x1b[{}b", n - 1)?;
+                        self.record_run(x, y, n, nc);
 
-                    match f.fg {
-                        Colour::Default => (),
-                        Colour::C16(c) => attrs.push(c),
-                        Colour::C256(c) => {
-                            attrs.push(38);
-                            attrs.push(5);
-                            attrs.push(c);
-                        }
-                        Colour::RGB(r, g, b) => {
-                            attrs.push(38);
-                            attrs.push(2);
-                            attrs.push(r);
-                            attrs.push(g);
-                            attrs.push(b);
-                        }
-                        Colour::UseExisting => panic!("should not get here"),
+                        x += n;
+                        contig = true;
+                        cursor = Some(self.after(x, y));
+                        continue;
                     }
+                }
 
-                    match f.bg {
-                        Colour::Default => (),
-                        Colour::C16(c) => attrs.push(c),
-                        Colour::C256(c) => {
-                            attrs.push(48);
-                            attrs.push(5);
-                            attrs.push(c);
-                        }
-                        Colour::RGB(r, g, b) => {
-                            attrs.push(48);
-                            attrs.push(2);
-                            attrs.push(r);
-                            attrs.push(g);
-                            attrs.push(b);
-                        }
-                        Colour::UseExisting => panic!("should not get here"),
-                    }
+                if nc.is_continuation() {
+                    /*
+                     * This should not happen if the leading column of the
+                     * wide character is intact, but if it does we must still
+                     * put something in the cell.
+                     */
+                    out.push(b' ');
+                } else {
+                    out.extend_from_slice(nc.contents().as_bytes());
+                }
+
+                /*
+                 * Update our record of what has been drawn to the screen.
+                 * Any trailing columns covered by a wide character are now
+                 * continuation cells.
+                 */
+                self.screen.cell_mut(x, y).unwrap().set_from(nc);
+                for cx in (x + 1)..(x + w).min(width) {
+                    self.screen
+                        .cell_mut(cx, y)
+                        .unwrap()
+                        .continuation(nc.format());
+                }
+                x += w;
 
-                    let s = attrs
-                        .iter()
-                        .map(|n| n.to_string())
-                        .collect::<Vec<_>>()
-                        .join(";");
-                    out += &format!("\x1b[{}m", s);
+                contig = true;
+                cursor = Some(self.after(x, y));
+            }
+        }
 
-                    last_format = Some(*f);
+        self.invalid.fill(false);
+        for y in 0..height {
+            let stamp =
+                if stamped { r.row_stamp(y) } else { RowStamp::default() };
+            if stamp == RowStamp::default() || stamp != self.stamps[y] {
+                self.hashes[y] = None;
+            }
+            self.stamps[y] = stamp;
+        }
+
+        /*
+         * Put the cursor back where the region wants it, or hide it if the
+         * region has no cursor.
+         */
+        self.cursor_at = cursor;
+        if r.cursor_style() != self.cursor_style {
+            write_cursor_style(out, &self.cursor_style, &r.cursor_style())?;
+            self.cursor_style = r.cursor_style();
+        }
+        match r.cursor().filter(|p| p.x < width && p.y < height) {
+            Some(p) => {
+                if self.cursor_at != Some(p) {
+                    let (from, vpa) = (self.cursor_at, self.row_address);
+                    move_cursor(out, from, p, width, vpa, None);
+                    self.cursor_at = Some(p);
+                }
+                if !self.cursor_visible {
+                    out.extend_from_slice(b"\x1b[?25h");
+                    self.cursor_visible = true;
                 }
+            }
+            None => {
+                if self.cursor_visible {
+                    out.extend_from_slice(b"\x1b[?25l");
+                    self.cursor_visible = false;
+                }
+            }
+        }
 
-                out.push(nc.contents());
-                x += nc.width();
+        Ok(())
+    }
+}
 
-                contig = true;
-                last_row = Some(y);
-                last_col = Some(x - 1);
+/**
+ * How to draw a run of identical cells; see Draw::run().
+ */
+enum Run {
+    Single,
+    EraseLine,
+    Erase(usize),
+    Repeat(usize),
+}
 
-                /*
-                 * Update our record of what has been drawn to the screen.
-                 */
-                oc.set_from(&nc);
+fn row_hash(row: &[Cell]) -> u64 {
+    let mut h = DefaultHasher::new();
+    row.hash(&mut h);
+    h.finish()
+}
+
+/**
+ * Emit the sequence that moves the rows from "top" to "bottom" inclusive up
+ * (or down) by "n" rows.  If the block extends to the bottom of the screen,
+ * deleting (or inserting) lines at the top of the block has the same effect
+ * without needing to set a scrolling region.
+ */
+fn write_scroll(
+    out: &mut Vec<u8>,
+    top: usize,
+    bottom: usize,
+    height: usize,
+    n: usize,
+    up: bool,
+) -> io::Result<()> {
+    if top == 0 && bottom == height - 1 {
+        write!(out, "\x1b[{}{}", n, if up { 'S' } else { 'T' })
+    } else if bottom == height - 1 {
+        write!(
+            out,
+            "\x1b[{};1f\x1b[{}{}",
+            top + 1,
+            n,
+            if up { 'M' } else { 'L' }
+        )
+    } else {
+        write!(
+            out,
+            "\x1b[{};{}r\x1b[{}{}\x1b[r",
+            top + 1,
+            bottom + 1,
+            n,
+            if up { 'S' } else { 'T' }
+        )
+    }
+}
+
+/**
+ * Emit the SGR sequence for a transition from one format to another.  If the
+ * current state of the terminal is not known, all attributes are reset first.
+ * Otherwise, we emit only the attributes that have changed, unless it would be
+ * shorter to reset everything and start again.
+ */
+fn write_format(
+    out: &mut Vec<u8>,
+    from: Option<&Format>,
+    to: &Format,
+) -> io::Result<()> {
+    if from == Some(to) {
+        return Ok(());
+    }
+
+    let start = out.len();
+    write_format_reset(out, to)?;
+
+    let Some(from) = from else {
+        return Ok(());
+    };
+
+    /*
+     * Render the delta encoding as well, and then discard whichever of the two
+     * is longer.
+     */
+    let mid = out.len();
+    write_format_delta(out, from, to)?;
+    if out.len() - mid < mid - start {
+        out.drain(start..mid);
+    } else {
+        out.truncate(mid);
+    }
+
+    Ok(())
+}
+
+/**
+ * Emit the sequences required to change the shape and colour of the cursor.
+ * Shape is set with DECSCUSR, and colour with the xterm OSC 12 sequence.
+ */
+fn write_cursor_style(
+    out: &mut Vec<u8>,
+    from: &CursorStyle,
+    to: &CursorStyle,
+) -> io::Result<()> {
+    let decscusr = |s: &CursorStyle| match (s.shape, s.blink) {
+        (CursorShape::Default, _) => 0,
+        (CursorShape::Block, true) => 1,
+        (CursorShape::Block, false) => 2,
+        (CursorShape::Underline, true) => 3,
+        (CursorShape::Underline, false) => 4,
+        (CursorShape::Bar, true) => 5,
+        (CursorShape::Bar, false) => 6,
+    };
+
+    if decscusr(from) != decscusr(to) {
+        write!(out, "\x1b[{} q", decscusr(to))?;
+    }
+
+    if from.colour != to.colour {
+        let rgb = match to.colour {
+            Colour::Default | Colour::UseExisting => None,
+            Colour::C16(idx) if idx > 15 => None,
+            Colour::C16(idx) | Colour::C256(idx) => Some(rgb256(idx)),
+            Colour::RGB(r, g, b) => Some((r, g, b)),
+        };
+
+        match rgb {
+            Some((r, g, b)) => {
+                write!(out, "\x1b]12;#{:02x}{:02x}{:02x}\x07", r, g, b)?
             }
+            None => out.extend_from_slice(b"\x1b]112\x07"),
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Simple on/off attributes, along with the SGR parameters that enable and
+ * disable each of them.  Bold and dim are handled separately, as they share a
+ * single parameter to turn them off.
+ */
+fn flags(f: &Format) -> [(bool, &'static [u8], &'static [u8]); 6] {
+    [
+        (f.italic, b"3", b"23"),
+        (f.blink, b"5", b"25"),
+        (f.reverse, b"7", b"27"),
+        (f.hidden, b"8", b"28"),
+        (f.strikethrough, b"9", b"29"),
+        (f.overline, b"53", b"55"),
+    ]
+}
+
+/**
+ * Emit the SGR sequence that resets all attributes and then establishes the
+ * provided format.
+ */
+fn write_format_reset(out: &mut Vec<u8>, f: &Format) -> io::Result<()> {
+    out.extend_from_slice(b"\x1b[0");
+
+    if f.bold {
+        out.extend_from_slice(b";1");
+    }
+
+    if f.dim {
+        out.extend_from_slice(b";2");
+    }
+
+    if f.underline != Underline::None {
+        out.push(b';');
+        write_underline(out, f.underline);
+    }
+
+    for (on, param, _) in flags(f) {
+        if on {
+            out.push(b';');
+            out.extend_from_slice(param);
+        }
+    }
+
+    if f.fg != Colour::Default {
+        out.push(b';');
+        write_colour(out, f.fg, Layer::Fg)?;
+    }
+
+    if f.bg != Colour::Default {
+        out.push(b';');
+        write_colour(out, f.bg, Layer::Bg)?;
+    }
+
+    if f.underline_colour != Colour::Default {
+        out.push(b';');
+        write_colour(out, f.underline_colour, Layer::Underline)?;
+    }
+
+    out.push(b'm');
+    Ok(())
+}
+
+/**
+ * Emit an SGR sequence that changes only those attributes that differ between
+ * the two formats.
+ */
+fn write_format_delta(
+    out: &mut Vec<u8>,
+    from: &Format,
+    to: &Format,
+) -> io::Result<()> {
+    let mut first = true;
+    let mut sep = |out: &mut Vec<u8>| {
+        if !std::mem::take(&mut first) {
+            out.push(b';');
+        }
+    };
+
+    out.extend_from_slice(b"\x1b[");
+
+    /*
+     * Bold and dim are both turned off by the same parameter, so if either of
+     * them is going away we must turn both off and then re-enable whichever
+     * one should remain.
+     */
+    let normal = (from.bold && !to.bold) || (from.dim && !to.dim);
+    if normal {
+        sep(out);
+        out.extend_from_slice(b"22");
+    }
+
+    if to.bold && (normal || !from.bold) {
+        sep(out);
+        out.push(b'1');
+    }
+
+    if to.dim && (normal || !from.dim) {
+        sep(out);
+        out.push(b'2');
+    }
+
+    if from.underline != to.underline {
+        sep(out);
+        write_underline(out, to.underline);
+    }
+
+    for ((was, _, _), (now, on, off)) in flags(from).into_iter().zip(flags(to))
+    {
+        if was != now {
+            sep(out);
+            out.extend_from_slice(if now { on } else { off });
+        }
+    }
+
+    if from.fg != to.fg {
+        sep(out);
+        write_colour(out, to.fg, Layer::Fg)?;
+    }
+
+    if from.bg != to.bg {
+        sep(out);
+        write_colour(out, to.bg, Layer::Bg)?;
+    }
+
+    if from.underline_colour != to.underline_colour {
+        sep(out);
+        write_colour(out, to.underline_colour, Layer::Underline)?;
+    }
+
+    out.push(b'm');
+    Ok(())
+}
+
+fn write_underline(out: &mut Vec<u8>, u: Underline) {
+    out.extend_from_slice(match u {
+        Underline::None => b"24",
+        Underline::Single => b"4",
+        Underline::Double => b"4:2",
+        Underline::Curly => b"4:3",
+        Underline::Dotted => b"4:4",
+        Underline::Dashed => b"4:5",
+    });
+}
+
+/**
+ * The part of a cell to which a colour applies.
+ */
+#[derive(Clone, Copy)]
+enum Layer {
+    Fg,
+    Bg,
+    Underline,
+}
+
+/**
+ * Emit the SGR parameters that select a colour for the foreground, background,
+ * or underline.
+ */
+fn write_colour(out: &mut Vec<u8>, c: Colour, layer: Layer) -> io::Result<()> {
+    let base = match layer {
+        Layer::Fg => 30,
+        Layer::Bg => 40,
+        Layer::Underline => 50,
+    };
+
+    match (c, layer) {
+        (Colour::Default | Colour::UseExisting, _) => {
+            /*
+             * A transparent cell drawn directly to the screen has nothing
+             * beneath it, so we use the default colour.
+             */
+            write!(out, "{}", base + 9)
+        }
+        (Colour::C16(idx), _) if idx > 15 => {
+            /*
+             * This is not a valid palette index.  Rather than emit a
+             * parameter that may select some unrelated attribute, use the
+             * default colour.
+             */
+            write!(out, "{}", base + 9)
+        }
+        (Colour::C16(idx), Layer::Underline) => {
+            /*
+             * There is no dedicated range of parameters for 16 colour
+             * underlines, but the first 16 entries of the 256 colour palette
+             * are the same colours.
+             */
+            write!(out, "{};5;{}", base + 8, idx)
+        }
+        (Colour::C16(idx), _) if idx < 8 => write!(out, "{}", base + idx),
+        (Colour::C16(idx), _) => write!(out, "{}", base + 60 + idx - 8),
+        (Colour::C256(c), _) => write!(out, "{};5;{}", base + 8, c),
+        (Colour::RGB(r, g, b), _) => {
+            write!(out, "{};2;{};{};{}", base + 8, r, g, b)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sgr(from: Option<&Format>, to: &Format) -> String {
+        let mut out = Vec::new();
+        write_format(&mut out, from, to).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn format_from_unknown_state_resets() {
+        let bold = Format { bold: true, ..Default::default() };
+        assert_eq!(sgr(None, &bold), "\x1b[0;1m");
+        assert_eq!(sgr(None, &Format::default()), "\x1b[0m");
+
+        let colours = Format {
+            reverse: true,
+            fg: Colour::C256(20),
+            bg: Colour::RGB(1, 2, 3),
+            ..Default::default()
+        };
+        assert_eq!(sgr(None, &colours), "\x1b[0;7;38;5;20;48;2;1;2;3m");
+    }
+
+    #[test]
+    fn format_chooses_shorter_of_reset_and_delta() {
+        let plain = Format::default();
+        let bold = Format { bold: true, ..Default::default() };
+        let busy = Format {
+            bold: true,
+            reverse: true,
+            fg: Colour::C256(20),
+            bg: Colour::RGB(1, 2, 3),
+            ..Default::default()
+        };
+
+        assert_eq!(sgr(Some(&plain), &bold), "\x1b[1m");
+        assert_eq!(sgr(Some(&bold), &plain), "\x1b[0m");
+        assert_eq!(sgr(Some(&busy), &plain), "\x1b[0m");
+        assert_eq!(
+            sgr(Some(&busy), &Format { reverse: false, ..busy }),
+            "\x1b[27m"
+        );
+        assert_eq!(
+            sgr(
+                Some(&busy),
+                &Format { bold: false, fg: Colour::Default, ..busy }
+            ),
+            "\x1b[22;39m"
+        );
+        assert_eq!(
+            sgr(Some(&busy), &Format { bg: Colour::C256(7), ..busy }),
+            "\x1b[48;5;7m"
+        );
+    }
+
+    #[test]
+    fn format_unchanged() {
+        /*
+         * An empty SGR sequence would reset every attribute.
+         */
+        let bold = Format { bold: true, ..Default::default() };
+        assert_eq!(sgr(Some(&bold), &bold), "");
+    }
+
+    #[test]
+    fn format_attributes() {
+        let all = Format {
+            bold: true,
+            dim: true,
+            italic: true,
+            underline: Underline::Double,
+            blink: true,
+            reverse: true,
+            hidden: true,
+            strikethrough: true,
+            overline: true,
+            ..Default::default()
+        };
+        assert_eq!(sgr(None, &all), "\x1b[0;1;2;4:2;3;5;7;8;9;53m");
+        assert_eq!(
+            sgr(Some(&all), &Format { italic: false, overline: false, ..all }),
+            "\x1b[23;55m"
+        );
+    }
+
+    #[test]
+    fn format_bold_and_dim_share_reset() {
+        let fg = Colour::C256(1);
+        let both = Format { bold: true, dim: true, fg, ..Default::default() };
+        let dim = Format { dim: true, fg, ..Default::default() };
+        let bold = Format { bold: true, fg, ..Default::default() };
+
+        assert_eq!(sgr(Some(&both), &dim), "\x1b[22;2m");
+        assert_eq!(sgr(Some(&both), &bold), "\x1b[22;1m");
+        assert_eq!(sgr(Some(&dim), &both), "\x1b[1m");
+    }
+
+    #[test]
+    fn format_underline_transitions() {
+        let base = Format {
+            bold: true,
+            fg: Colour::C256(2),
+            underline: Underline::Single,
+            underline_colour: Colour::C256(5),
+            ..Default::default()
+        };
+        let curly = Format {
+            underline: Underline::Curly,
+            underline_colour: Colour::RGB(1, 2, 3),
+            ..base
+        };
+        let none = Format {
+            underline: Underline::None,
+            underline_colour: Colour::Default,
+            ..base
+        };
+
+        assert_eq!(sgr(Some(&base), &curly), "\x1b[4:3;58;2;1;2;3m");
+        assert_eq!(sgr(Some(&base), &none), "\x1b[24;59m");
+        assert_eq!(
+            sgr(
+                Some(&none),
+                &Format { underline_colour: Colour::C256(9), ..none }
+            ),
+            "\x1b[58;5;9m"
+        );
+    }
+
+    #[test]
+    fn format_palette_colours() {
+        let f = Format {
+            fg: Colour::C16(3),
+            bg: Colour::C16(12),
+            underline_colour: Colour::C16(9),
+            ..Default::default()
+        };
+        assert_eq!(sgr(None, &f), "\x1b[0;33;104;58;5;9m");
+
+        /*
+         * Out of range indexes fall back on the default colour.
+         */
+        let bad = Format { fg: Colour::C16(33), ..Default::default() };
+        assert_eq!(sgr(Some(&f), &bad), "\x1b[0;39m");
+        assert_eq!(sgr(None, &bad), "\x1b[0;39m");
+    }
+
+    #[test]
+    fn invalidate_rect_through_wide_character() {
+        let mut r = Region::new(10, 1);
+        r.str(0, 0, "ab中");
+
+        let mut d = Draw::new(10, 1);
+        d.apply(&r);
+
+        /*
+         * Change the cell to the left of the wide character, and invalidate
+         * only its trailing half, so that the whole character must be drawn
+         * again immediately after the changed cell.
+         */
+        r.str(1, 0, "c");
+        d.invalidate_rect(Rect::new(3, 0, 1, 1));
+        let out = d.apply(&r);
+
+        assert!(out.ends_with("c中"), "{:?}", out);
+        assert!(!out.contains("\x1b[0C"), "{:?}", out);
+        assert_eq!(d.apply(&r), "");
+    }
+
+    /*
+     * A frame in which each row is filled with one of the given characters,
+     * with a space standing for a blank row.
+     */
+    fn frame(rows: &str, width: usize) -> Region {
+        let mut r = Region::new(width, rows.len());
+        for (y, ch) in rows.chars().enumerate() {
+            r.str(0, y, &ch.to_string().repeat(width));
         }
+        r
+    }
 
+    /*
+     * Draw the first frame, then return the output for the second.
+     */
+    fn scrolled(first: &str, second: &str, width: usize) -> String {
+        let mut d = Draw::new(width, first.len());
+        d.set_scroll_region(true);
+        d.apply(&frame(first, width));
+        let out = d.apply(&frame(second, width));
+        assert_eq!(d.apply(&frame(second, width)), "");
         out
     }
+
+    #[test]
+    fn scroll_whole_screen() {
+        assert_eq!(scrolled("abcd", "bcde", 4), "\x1b[0m\x1b[1S\x1b[4feeee");
+        assert_eq!(scrolled("bcde", "abcd", 4), "\x1b[0m\x1b[1T\x1b[faaaa");
+
+        /*
+         * Nothing needs to be drawn when the uncovered rows are blank.
+         */
+        assert_eq!(scrolled("abcd", "cd  ", 4), "\x1b[0m\x1b[2S");
+    }
+
+    #[test]
+    fn scroll_below_fixed_header() {
+        /*
+         * When the block reaches the bottom of the screen, deleting or
+         * inserting lines avoids the need for a scrolling region.
+         */
+        assert_eq!(
+            scrolled("habc", "hbcd", 12),
+            "\x1b[0m\x1b[2;1f\x1b[1M\x1b[4fdddddddddddd"
+        );
+        assert_eq!(
+            scrolled("hbcd", "habc", 12),
+            "\x1b[0m\x1b[2;1f\x1b[1L\x1b[2faaaaaaaaaaaa"
+        );
+    }
+
+    #[test]
+    fn scroll_between_header_and_footer() {
+        assert_eq!(
+            scrolled("habcf", "hbcdf", 12),
+            "\x1b[0m\x1b[2;4r\x1b[1S\x1b[r\x1b[4fdddddddddddd"
+        );
+        assert_eq!(
+            scrolled("habcf", "hzabf", 12),
+            "\x1b[0m\x1b[2;4r\x1b[1T\x1b[r\x1b[2fzzzzzzzzzzzz"
+        );
+    }
+
+    #[test]
+    fn scroll_not_worthwhile() {
+        /*
+         * With short rows, redrawing them is cheaper than the sequences
+         * needed to scroll.
+         */
+        assert_eq!(scrolled("abc", "bcd", 1), "\x1b[f\x1b[0mbcd");
+        assert_eq!(scrolled("habcf", "hbcdf", 4), "\x1b[2f\x1b[0mbbbbccccdddd");
+    }
+
+    #[test]
+    fn scroll_needs_capability() {
+        let mut d = Draw::new(4, 4);
+        d.apply(&frame("abcd", 4));
+        assert_eq!(d.apply(&frame("bcde", 4)), "\x1b[f\x1b[0mbbbbccccddddeeee");
+
+        let caps = Capabilities { scroll_region: true, ..Default::default() };
+        let mut d = Draw::with_capabilities(4, 4, &caps);
+        d.apply(&frame("abcd", 4));
+        assert_eq!(d.apply(&frame("bcde", 4)), "\x1b[0m\x1b[1S\x1b[4feeee");
+    }
+
+    fn runs(first: &Region, second: &Region, caps: &Capabilities) -> String {
+        let mut d =
+            Draw::with_capabilities(first.width(), first.height(), caps);
+        d.apply(first);
+        let out = d.apply(second);
+        assert_eq!(d.apply(second), "");
+        out
+    }
+
+    fn all_runs() -> Capabilities {
+        Capabilities {
+            erase_chars: true,
+            repeat_char: true,
+            back_colour_erase: true,
+            ..Default::default()
+        }
+    }
+
+    /*
+     * A row of "x" in which "n" cells from the second column on are replaced
+     * with the given character.
+     */
+    fn patched(width: usize, n: usize, ch: char) -> (Region, Region) {
+        let mut old = Region::new(width, 1);
+        old.str(0, 0, &"x".repeat(width));
+        let mut new = Region::new(width, 1);
+        new.str(0, 0, &"x".repeat(width));
+        new.str(1, 0, &ch.to_string().repeat(n));
+        (old, new)
+    }
+
+    #[test]
+    fn runs_disabled_by_default() {
+        /*
+         * Only EL, which every terminal has, is used unless the terminal is
+         * known to support the others.
+         */
+        let caps = Capabilities::default();
+        let (old, new) = patched(12, 9, 'y');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0myyyyyyyyy");
+        let (old, new) = patched(12, 9, ' ');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0m         ");
+        let (old, new) = patched(12, 11, ' ');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0m\x1b[K");
+
+        let d = Draw::new(1, 1);
+        assert!(!d.erase_chars && !d.repeat_char && !d.back_colour_erase);
+    }
+
+    #[test]
+    fn repeat_cut_over() {
+        /*
+         * "y" followed by REP costs five bytes for up to ten cells.
+         */
+        let caps = Capabilities { repeat_char: true, ..Default::default() };
+        let (old, new) = patched(12, 5, 'y');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0myyyyy");
+        let (old, new) = patched(12, 6, 'y');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0my\x1b[5b");
+        let (old, new) = patched(13, 11, 'y');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0my\x1b[10b");
+
+        /*
+         * The cost is measured in bytes, so multi-byte characters repeat
+         * sooner.  Wide characters are never repeated.
+         */
+        let mut new = Region::new(8, 1);
+        new.str(0, 0, "éééé中中");
+        assert_eq!(
+            runs(&Region::new(8, 1), &new, &caps),
+            "\x1b[f\x1b[0mé\x1b[3b中中"
+        );
+    }
+
+    #[test]
+    fn erase_cut_over() {
+        /*
+         * ECH must be followed by a cursor movement, so together they cost
+         * eight bytes for up to nine cells.  A run that would not pay off
+         * may still do so one cell later.
+         */
+        let caps = Capabilities { erase_chars: true, ..Default::default() };
+        let (old, new) = patched(12, 8, ' ');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0m        ");
+        let (old, new) = patched(12, 9, ' ');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0m\x1b[9X");
+        let (old, new) = patched(12, 10, ' ');
+        assert_eq!(runs(&old, &new, &caps), "\x1b[2G\x1b[0m \x1b[9X");
+
+        /*
+         * Where both are available, REP is cheaper.
+         */
+        let (old, new) = patched(12, 9, ' ');
+        assert_eq!(runs(&old, &new, &all_runs()), "\x1b[2G\x1b[0m \x1b[8b");
+    }
+
+    #[test]
+    fn erase_line_cut_over() {
+        let caps = Capabilities::default();
+        let mut old = Region::new(8, 1);
+        old.str(0, 0, "xxxxxxxx");
+
+        let mut new = Region::new(8, 1);
+        new.str(0, 0, "xxxxx");
+        assert_eq!(runs(&old, &new, &caps), "\x1b[6G\x1b[0m   ");
+        new.str(0, 0, "xxxx ");
+        assert_eq!(runs(&old, &new, &caps), "\x1b[5G\x1b[0m\x1b[K");
+    }
+
+    #[test]
+    fn erase_needs_plain_background() {
+        /*
+         * Erased cells take the background colour only with "bce", and never
+         * any other attribute.
+         */
+        let red = Format { bg: Colour::C256(1), ..Default::default() };
+        let mut old = Region::new(8, 1);
+        old.str(0, 0, "xxxxxxxx");
+        let mut new = Region::new(8, 1);
+        new.strf(0, 0, "        ", &red);
+
+        assert_eq!(runs(&old, &new, &all_runs()), "\r\x1b[0;48;5;1m\x1b[K");
+        let no_bce = Capabilities { back_colour_erase: false, ..all_runs() };
+        assert_eq!(runs(&old, &new, &no_bce), "\r\x1b[0;48;5;1m \x1b[7b");
+        let ech = Capabilities { erase_chars: true, ..Default::default() };
+        assert_eq!(runs(&old, &new, &ech), "\r\x1b[0;48;5;1m        ");
+
+        let bold = Format { bold: true, ..Default::default() };
+        new.strf(0, 0, "        ", &bold);
+        assert_eq!(runs(&old, &new, &all_runs()), "\r\x1b[0;1m \x1b[7b");
+    }
+
+    #[test]
+    fn erase_after_pending_wrap() {
+        /*
+         * After drawing in the last column, the cursor must be moved before
+         * erasing from the start of the next row.
+         */
+        let mut old = Region::new(8, 2);
+        old.str(0, 0, "xxxxxxxx");
+        old.str(0, 1, "xxxxxxxx");
+        let mut new = Region::new(8, 2);
+        new.str(0, 0, "xxxxxxxy");
+        assert_eq!(
+            runs(&old, &new, &all_runs()),
+            "\x1b[1;8f\x1b[0my\n\r\x1b[K"
+        );
+    }
+
+    #[test]
+    fn repeat_avoids_bottom_right() {
+        let mut new = Region::new(8, 2);
+        new.str(0, 1, "yyyyyyyy");
+        assert_eq!(
+            runs(&Region::new(8, 2), &new, &all_runs()),
+            "\x1b[2f\x1b[0my\x1b[7b"
+        );
+
+        let caps = Capabilities { line_glitch: false, ..all_runs() };
+        assert_eq!(
+            runs(&Region::new(8, 2), &new, &caps),
+            "\x1b[2f\x1b[0my\x1b[6b"
+        );
+    }
+
+    /*
+     * Draw a frame, then corrupt the first row of the cached screen so that
+     * the output shows whether that row is compared again or skipped.
+     */
+    fn stale(r: &Region) -> Draw {
+        let mut d = Draw::new(r.width(), r.height());
+        d.apply(r);
+        d.screen.str(0, 0, "####");
+        d
+    }
+
+    #[test]
+    fn stamp_skips_unchanged_rows() {
+        let r = frame("ab", 4);
+        let mut d = stale(&r);
+        assert_eq!(d.apply(&r), "");
+    }
+
+    #[test]
+    fn stamp_cell_mut() {
+        let mut r = frame("ab", 4);
+        let mut d = stale(&r);
+        r.cell_mut(1, 0);
+        assert_eq!(d.apply(&r), "\x1b[f\x1b[0maaaa");
+        assert_eq!(d.apply(&r), "");
+    }
+
+    #[test]
+    fn stamp_view_write() {
+        let mut r = frame("ab", 4);
+        let mut d = stale(&r);
+        r.view_mut(0, 0, 4, 1).str(0, 0, "aaaa");
+        assert_eq!(d.apply(&r), "\x1b[f\x1b[0maaaa");
+        assert_eq!(d.apply(&r), "");
+    }
+
+    #[test]
+    fn stamp_resize() {
+        let r = frame("ab", 4);
+        let mut d = stale(&r);
+        d.resize(4, 2);
+        assert_eq!(d.apply(&r), "\x1b[0m\x1b[H\x1b[2J\x1b[f\x1b[0maaaabbbb");
+    }
+
+    #[test]
+    fn stamp_other_region() {
+        let r = frame("ab", 4);
+        let mut d = stale(&r);
+        assert_eq!(d.apply(&frame("ab", 4)), "\x1b[f\x1b[0maaaa");
+        assert_eq!(d.apply(&r), "");
+    }
+
+    #[test]
+    fn synchronized_output_wraps_frames() {
+        let mut plain = Draw::new(10, 2);
+        let mut synced = Draw::new(10, 2);
+        synced.set_synchronized_output(true);
+
+        let mut r = Region::new(10, 2);
+        for (x, y, text) in [(0, 0, "ab"), (5, 1, "cd"), (7, 1, "e")] {
+            r.str(x, y, text);
+            let expected = plain.apply(&r);
+            assert!(!expected.is_empty());
+            assert_eq!(
+                synced.apply(&r),
+                format!("\x1b[?2026h{}\x1b[?2026l", expected)
+            );
+
+            /*
+             * Nothing at all is written for an unchanged frame.
+             */
+            assert_eq!(plain.apply(&r), "");
+            assert_eq!(synced.apply(&r), "");
+        }
+    }
 }
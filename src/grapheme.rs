@@ -0,0 +1,74 @@
+use unicode_width::UnicodeWidthStr;
+
+/*
+ * Clusters of up to this many bytes are stored without a heap allocation.
+ * Every single character fits, as do many short base-plus-combining-mark
+ * sequences.
+ */
+const INLINE_MAX: usize = 4;
+
+/**
+ * A single extended grapheme cluster; i.e., the text that will be drawn in
+ * one terminal cell.
+ */
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Grapheme {
+    Inline { len: u8, buf: [u8; INLINE_MAX] },
+    Heap(Box<str>),
+}
+
+impl Grapheme {
+    pub(crate) fn from_char(ch: char) -> Grapheme {
+        let mut buf = [0u8; INLINE_MAX];
+        let len = ch.encode_utf8(&mut buf).len() as u8;
+        Grapheme::Inline { len, buf }
+    }
+
+    pub(crate) fn from_str(s: &str) -> Grapheme {
+        if s.len() <= INLINE_MAX {
+            let mut buf = [0u8; INLINE_MAX];
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            Grapheme::Inline { len: s.len() as u8, buf }
+        } else {
+            Grapheme::Heap(s.into())
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Grapheme::Inline { len, buf } => {
+                std::str::from_utf8(&buf[..*len as usize]).unwrap()
+            }
+            Grapheme::Heap(s) => s,
+        }
+    }
+
+    /**
+     * Append zero-width characters (e.g., combining marks) to the cluster.
+     */
+    pub(crate) fn push_str(&mut self, extra: &str) {
+        let mut s = self.as_str().to_string();
+        s.push_str(extra);
+        *self = Grapheme::from_str(&s);
+    }
+}
+
+impl Default for Grapheme {
+    fn default() -> Self {
+        Grapheme::from_char(' ')
+    }
+}
+
+/**
+ * Determine the number of terminal columns occupied by a single grapheme
+ * cluster.  A cluster is never wider than two columns, regardless of how many
+ * characters make it up.  Control characters have no sensible display width
+ * and are reported as zero, just like combining marks.
+ */
+pub(crate) fn cluster_width(g: &str) -> usize {
+    if g.chars().any(|ch| ch.is_control()) {
+        0
+    } else {
+        g.width().min(2)
+    }
+}
@@ -0,0 +1,500 @@
+use super::grapheme::cluster_width;
+use super::region::*;
+use unicode_segmentation::UnicodeSegmentation;
+
+/**
+ * How text that is wider than the space available is broken into lines.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Wrap {
+    /**
+     * Lines are broken only at newlines, and are truncated if too wide.
+     */
+    None,
+    /**
+     * Lines are broken between words where possible.  A word too wide to fit
+     * on a line of its own is broken wherever it must be.
+     */
+    #[default]
+    Word,
+    /**
+     * Lines are broken at whichever character reaches the edge.
+     */
+    Char,
+}
+
+/**
+ * The vertical placement of text within a taller space.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/**
+ * Options for laying out text within a rectangle.  If the text does not fit,
+ * the ellipsis is placed at the end of the last line that does.  An empty
+ * ellipsis means that text is truncated without any indication.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextLayout {
+    pub wrap: Wrap,
+    pub align: Align,
+    pub valign: VAlign,
+    pub ellipsis: String,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        TextLayout {
+            wrap: Wrap::Word,
+            align: Align::Left,
+            valign: VAlign::Top,
+            ellipsis: "…".to_string(),
+        }
+    }
+}
+
+/**
+ * A line of wrapped text, as a byte range within the original string, along
+ * with its display width.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct Wrapped {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) width: usize,
+}
+
+/**
+ * A line of text as it is to be drawn: the part of the line that is visible,
+ * the column at which it begins relative to the left edge, and whether the
+ * ellipsis follows it.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct Placed {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) ellipsis: bool,
+}
+
+pub(crate) fn str_width(s: &str) -> usize {
+    s.graphemes(true).map(cluster_width).sum()
+}
+
+/**
+ * Find the longest prefix of a string that fits in the given width, returning
+ * its length in bytes and its display width.
+ */
+pub(crate) fn truncate(s: &str, width: usize) -> (usize, usize) {
+    let mut w = 0;
+    for (i, g) in s.grapheme_indices(true) {
+        let gw = cluster_width(g);
+        if w + gw > width {
+            return (i, w);
+        }
+        w += gw;
+    }
+    (s.len(), w)
+}
+
+/**
+ * Break a string into lines that are no wider than "width" columns.  Newlines
+ * always begin a new line.  Whitespace at the point where a line is broken
+ * between words is discarded.
+ */
+pub(crate) fn wrap(s: &str, width: usize, mode: Wrap) -> Vec<Wrapped> {
+    let mut lines = Vec::new();
+    if width == 0 {
+        return lines;
+    }
+
+    let mut pstart = 0;
+    for raw in s.split('\n') {
+        let para = raw.strip_suffix('\r').unwrap_or(raw);
+        match mode {
+            Wrap::None => lines.push(Wrapped {
+                start: pstart,
+                end: pstart + para.len(),
+                width: str_width(para),
+            }),
+            Wrap::Char => {
+                wrap_chars(&mut lines, para, pstart, width, true);
+            }
+            Wrap::Word => wrap_words(&mut lines, para, pstart, width),
+        }
+        pstart += raw.len() + 1;
+    }
+
+    lines
+}
+
+/**
+ * Break text into lines at whichever grapheme reaches the edge.  Unless this
+ * is the last piece of a paragraph, the final partial line is left open (and
+ * returned) so that more words can be added to it.
+ */
+fn wrap_chars(
+    lines: &mut Vec<Wrapped>,
+    s: &str,
+    offset: usize,
+    width: usize,
+    last: bool,
+) -> Option<Wrapped> {
+    let mut line = Wrapped { start: offset, end: offset, width: 0 };
+
+    for (i, g) in s.grapheme_indices(true) {
+        let gw = cluster_width(g);
+        if line.width + gw > width && line.end > line.start {
+            lines.push(line);
+            line = Wrapped { start: offset + i, end: offset + i, width: 0 };
+        }
+        line.end = offset + i + g.len();
+        line.width += gw;
+    }
+
+    if last {
+        lines.push(line);
+        None
+    } else {
+        Some(line)
+    }
+}
+
+/**
+ * Split a string into alternating runs of whitespace and non-whitespace.
+ */
+fn words(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut gs = s.grapheme_indices(true).peekable();
+    std::iter::from_fn(move || {
+        let (start, g) = gs.next()?;
+        let space = g.chars().all(char::is_whitespace);
+        let mut end = start + g.len();
+        while let Some((i, g)) =
+            gs.next_if(|(_, g)| g.chars().all(char::is_whitespace) == space)
+        {
+            end = i + g.len();
+        }
+        Some((start, &s[start..end]))
+    })
+}
+
+fn wrap_words(
+    lines: &mut Vec<Wrapped>,
+    para: &str,
+    offset: usize,
+    width: usize,
+) {
+    let mut line: Option<Wrapped> = None;
+    let mut space = 0;
+
+    /*
+     * A run of whitespace at the start of the paragraph is kept, as it is
+     * likely to be indentation.  Elsewhere, whitespace is only included in
+     * the line if followed by a word that also fits.
+     */
+    for (i, word) in words(para) {
+        let start = offset + i;
+        let w = str_width(word);
+
+        if word.chars().all(char::is_whitespace) && i > 0 {
+            if line.is_some() {
+                space += w;
+            }
+            continue;
+        }
+
+        match line.as_mut() {
+            Some(l) if l.width + space + w <= width => {
+                l.end = start + word.len();
+                l.width += space + w;
+            }
+            _ => {
+                if let Some(l) = line.take() {
+                    lines.push(l);
+                }
+                if w <= width {
+                    line = Some(Wrapped {
+                        start,
+                        end: start + word.len(),
+                        width: w,
+                    });
+                } else {
+                    line = wrap_chars(lines, word, start, width, false);
+                }
+            }
+        }
+        space = 0;
+    }
+
+    lines.push(line.unwrap_or(Wrapped {
+        start: offset,
+        end: offset,
+        width: 0,
+    }));
+}
+
+impl TextLayout {
+    /**
+     * Determine the number of lines needed to lay out the text in the given
+     * width, were there no limit on the height.
+     */
+    pub fn measure(&self, s: &str, width: usize) -> usize {
+        wrap(s, width, self.wrap).len()
+    }
+
+    /**
+     * Work out where each visible line of text should be drawn within an area
+     * of the given size.
+     */
+    pub(crate) fn place(
+        &self,
+        s: &str,
+        width: usize,
+        height: usize,
+    ) -> Vec<Placed> {
+        let lines = wrap(s, width, self.wrap);
+        let used = lines.len().min(height);
+        let ew = str_width(&self.ellipsis);
+
+        let top = match self.valign {
+            VAlign::Top => 0,
+            VAlign::Middle => (height - used) / 2,
+            VAlign::Bottom => height - used,
+        };
+
+        lines
+            .iter()
+            .take(used)
+            .enumerate()
+            .map(|(i, l)| {
+                let more = i + 1 == used && used < lines.len();
+                let text = &s[l.start..l.end];
+
+                let (end, w, ellipsis) = if l.width <= width && !more {
+                    (l.end, l.width, false)
+                } else if ew > width {
+                    /*
+                     * There is not even room for the ellipsis.
+                     */
+                    let (len, w) = truncate(text, width);
+                    (l.start + len, w, false)
+                } else {
+                    let (len, w) = truncate(text, width - ew);
+                    (l.start + len, w + ew, ew > 0)
+                };
+
+                let slack = width.saturating_sub(w);
+                Placed {
+                    start: l.start,
+                    end,
+                    x: match self.align {
+                        Align::Left => 0,
+                        Align::Centre => slack / 2,
+                        Align::Right => slack,
+                    },
+                    y: top + i,
+                    ellipsis,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Region {
+    /**
+     * Draw text within a rectangle, wrapping, aligning, and truncating it as
+     * directed by the layout.  Returns the number of lines used.
+     */
+    pub fn text_layout(
+        &mut self,
+        r: Rect,
+        s: &str,
+        f: &Format,
+        layout: &TextLayout,
+    ) -> usize {
+        let r = r.clip(self.width(), self.height());
+        let (left, right) = (r.x, r.x + r.width);
+
+        let placed = layout.place(s, r.width, r.height);
+        for p in placed.iter() {
+            let (x, y) = (r.x + p.x, r.y + p.y);
+            let w = self.text(left, right, x, y, &s[p.start..p.end], Some(f));
+            if p.ellipsis {
+                self.text(left, right, x + w, y, &layout.ellipsis, Some(f));
+            }
+        }
+
+        placed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str, width: usize, mode: Wrap) -> Vec<&str> {
+        wrap(s, width, mode).iter().map(|l| &s[l.start..l.end]).collect()
+    }
+
+    fn place<'a>(
+        layout: &TextLayout,
+        s: &'a str,
+        width: usize,
+        height: usize,
+    ) -> Vec<(&'a str, usize, usize, bool)> {
+        layout
+            .place(s, width, height)
+            .iter()
+            .map(|p| (&s[p.start..p.end], p.x, p.y, p.ellipsis))
+            .collect()
+    }
+
+    #[test]
+    fn word_wrap() {
+        let s = "the quick brown fox";
+        assert_eq!(lines(s, 10, Wrap::Word), ["the quick", "brown fox"]);
+        assert_eq!(lines(s, 9, Wrap::Word), ["the quick", "brown fox"]);
+        assert_eq!(lines(s, 8, Wrap::Word), ["the", "quick", "brown", "fox"]);
+        assert_eq!(lines(s, 19, Wrap::Word), [s]);
+
+        /*
+         * Whitespace is dropped where a line is broken, but kept within a
+         * line and at the start of a paragraph.
+         */
+        assert_eq!(lines("aaa   bbb", 4, Wrap::Word), ["aaa", "bbb"]);
+        assert_eq!(lines("a  b", 4, Wrap::Word), ["a  b"]);
+        assert_eq!(lines("  ab cd", 5, Wrap::Word), ["  ab", "cd"]);
+        assert_eq!(lines("ab   ", 4, Wrap::Word), ["ab"]);
+
+        let widths: Vec<_> =
+            wrap(s, 10, Wrap::Word).iter().map(|l| l.width).collect();
+        assert_eq!(widths, [9, 9]);
+    }
+
+    #[test]
+    fn hard_breaks() {
+        let s = "ab\ncd\r\n\nef";
+        for mode in [Wrap::None, Wrap::Word, Wrap::Char] {
+            assert_eq!(lines(s, 10, mode), ["ab", "cd", "", "ef"]);
+        }
+        assert_eq!(lines("ab cd\n", 3, Wrap::Word), ["ab", "cd", ""]);
+        assert_eq!(lines("", 3, Wrap::Word), [""]);
+        assert!(wrap("ab", 0, Wrap::Word).is_empty());
+    }
+
+    #[test]
+    fn overlong_words() {
+        let s = "abcdefghij xy";
+        assert_eq!(lines(s, 4, Wrap::Word), ["abcd", "efgh", "ij", "xy"]);
+        assert_eq!(lines(s, 5, Wrap::Word), ["abcde", "fghij", "xy"]);
+        assert_eq!(lines(s, 6, Wrap::Word), ["abcdef", "ghij", "xy"]);
+        assert_eq!(lines(s, 7, Wrap::Word), ["abcdefg", "hij xy"]);
+        assert_eq!(lines("ab cdefghij", 4, Wrap::Word), ["ab", "cdef", "ghij"]);
+
+        assert_eq!(lines(s, 4, Wrap::Char), ["abcd", "efgh", "ij x", "y"]);
+        assert_eq!(lines(s, 20, Wrap::None), [s]);
+        assert_eq!(lines(s, 4, Wrap::None), [s]);
+    }
+
+    #[test]
+    fn wide_characters_at_edge() {
+        /*
+         * A wide character that would straddle the edge moves to the next
+         * line, leaving the last column empty.
+         */
+        assert_eq!(lines("ab中文", 3, Wrap::Char), ["ab", "中", "文"]);
+        assert_eq!(lines("ab中文", 4, Wrap::Char), ["ab中", "文"]);
+        assert_eq!(lines("中文字", 5, Wrap::Word), ["中文", "字"]);
+        assert_eq!(lines("a 中文", 3, Wrap::Word), ["a", "中", "文"]);
+
+        let widths: Vec<_> =
+            wrap("ab中文", 3, Wrap::Char).iter().map(|l| l.width).collect();
+        assert_eq!(widths, [2, 2, 2]);
+
+        /*
+         * Combining marks stay with their base character.
+         */
+        assert_eq!(lines("abe\u{301}d", 3, Wrap::Char), ["abe\u{301}", "d"]);
+    }
+
+    #[test]
+    fn horizontal_alignment() {
+        let mut layout = TextLayout::default();
+        assert_eq!(place(&layout, "ab", 6, 1), [("ab", 0, 0, false)]);
+        layout.align = Align::Centre;
+        assert_eq!(place(&layout, "ab", 6, 1), [("ab", 2, 0, false)]);
+        assert_eq!(place(&layout, "ab", 5, 1), [("ab", 1, 0, false)]);
+        layout.align = Align::Right;
+        assert_eq!(place(&layout, "ab", 6, 1), [("ab", 4, 0, false)]);
+
+        /*
+         * Each line is aligned on its own.
+         */
+        assert_eq!(
+            place(&layout, "abc de", 4, 2),
+            [("abc", 1, 0, false), ("de", 2, 1, false)]
+        );
+    }
+
+    #[test]
+    fn vertical_alignment() {
+        let s = "ab\ncd";
+        let mut layout = TextLayout::default();
+        let rows = |layout: &TextLayout, height| -> Vec<usize> {
+            place(layout, s, 4, height).iter().map(|p| p.2).collect()
+        };
+
+        assert_eq!(rows(&layout, 5), [0, 1]);
+        layout.valign = VAlign::Middle;
+        assert_eq!(rows(&layout, 5), [1, 2]);
+        assert_eq!(rows(&layout, 4), [1, 2]);
+        layout.valign = VAlign::Bottom;
+        assert_eq!(rows(&layout, 5), [3, 4]);
+
+        /*
+         * Text that fills the height is not moved.
+         */
+        for valign in [VAlign::Top, VAlign::Middle, VAlign::Bottom] {
+            layout.valign = valign;
+            assert_eq!(rows(&layout, 2), [0, 1]);
+            assert_eq!(rows(&layout, 1), [0]);
+        }
+    }
+
+    #[test]
+    fn ellipsis() {
+        let mut layout = TextLayout::default();
+
+        /*
+         * More lines than fit.
+         */
+        assert_eq!(
+            place(&layout, "ab cd ef", 3, 2),
+            [("ab", 0, 0, false), ("cd", 0, 1, true)]
+        );
+        assert_eq!(place(&layout, "abc def", 3, 1), [("ab", 0, 0, true)]);
+
+        /*
+         * A line that is too wide.
+         */
+        layout.wrap = Wrap::None;
+        layout.align = Align::Right;
+        assert_eq!(place(&layout, "abcdef", 4, 1), [("abc", 0, 0, true)]);
+        assert_eq!(place(&layout, "中文字", 4, 1), [("中", 1, 0, true)]);
+
+        /*
+         * Truncation without any indication.
+         */
+        layout.ellipsis = String::new();
+        assert_eq!(place(&layout, "abcdef", 4, 1), [("abcd", 0, 0, false)]);
+        layout.ellipsis = "...".to_string();
+        assert_eq!(place(&layout, "abcdef", 2, 1), [("ab", 0, 0, false)]);
+        assert_eq!(place(&layout, "abcdef", 4, 1), [("a", 0, 0, true)]);
+    }
+}
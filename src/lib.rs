@@ -1,5 +1,33 @@
+mod ansi;
+mod border;
+mod caps;
+mod colour;
+mod compositor;
 mod draw;
+mod grapheme;
+mod layout;
+mod mode;
+mod motion;
 mod region;
+#[cfg(unix)]
+mod resize;
+mod terminfo;
+mod text;
+mod view;
 
+pub use ansi::AnsiParser;
+pub use border::Border;
+pub use caps::Capabilities;
+pub use colour::ColourDepth;
+pub use compositor::{Compositor, LayerId};
 pub use draw::Draw;
-pub use region::{Cell, Colour, Format, Region};
+pub use layout::{TextLayout, VAlign, Wrap};
+pub use mode::{ModeReport, ModeState};
+pub use region::{
+    Align, Cell, Colour, CursorShape, CursorStyle, Format, Point, Rect, Region,
+    Underline,
+};
+#[cfg(unix)]
+pub use resize::{terminal_size, ResizeWatcher};
+pub use text::{Line, Span, Text};
+pub use view::View;
@@ -0,0 +1,212 @@
+use std::ops::Range;
+
+/**
+ * The state of a terminal mode, as reported in response to a DECRQM query.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModeState {
+    NotRecognised,
+    Set,
+    Reset,
+    PermanentlySet,
+    PermanentlyReset,
+}
+
+impl ModeState {
+    /**
+     * Whether the terminal recognises the mode and allows it to be set.
+     */
+    pub fn is_supported(&self) -> bool {
+        matches!(
+            self,
+            ModeState::Set | ModeState::Reset | ModeState::PermanentlySet
+        )
+    }
+}
+
+/**
+ * A report of the state of a terminal mode (DECRPM), which a terminal sends in
+ * response to a query (DECRQM) such as "CSI ? 2026 $ p".  The report takes
+ * the form "CSI ? mode ; state $ y", or the same without the "?" for modes
+ * that are not DEC private modes.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ModeReport {
+    pub mode: u32,
+    pub private: bool,
+    pub state: ModeState,
+}
+
+impl ModeReport {
+    /**
+     * Parse a mode report at the start of some input, returning the report
+     * and its length in bytes.
+     */
+    pub fn parse(buf: &[u8]) -> Option<(ModeReport, usize)> {
+        let rest = buf.strip_prefix(b"\x1b[")?;
+        let (private, rest) = match rest.strip_prefix(b"?") {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let (mode, rest) = number(rest)?;
+        let rest = rest.strip_prefix(b";")?;
+        let (state, rest) = number(rest)?;
+        let rest = rest.strip_prefix(b"$y")?;
+
+        let state = match state {
+            1 => ModeState::Set,
+            2 => ModeState::Reset,
+            3 => ModeState::PermanentlySet,
+            4 => ModeState::PermanentlyReset,
+            _ => ModeState::NotRecognised,
+        };
+
+        Some((ModeReport { mode, private, state }, buf.len() - rest.len()))
+    }
+
+    /**
+     * Search input read from the terminal for a mode report, which may be
+     * mixed in with other input such as keystrokes.  Returns the first report
+     * found along with the range of bytes that it occupies, so that the caller
+     * can remove it from the input.  A report that has only partly arrived is
+     * not found until the rest of it is read.
+     */
+    pub fn find(buf: &[u8]) -> Option<(ModeReport, Range<usize>)> {
+        (0..buf.len()).filter(|&i| buf[i] == 0x1B).find_map(|i| {
+            ModeReport::parse(&buf[i..])
+                .map(|(report, len)| (report, i..i + len))
+        })
+    }
+}
+
+/**
+ * Parse a decimal parameter, returning its value and the rest of the input.
+ */
+fn number(buf: &[u8]) -> Option<(u32, &[u8])> {
+    let len = buf.iter().take_while(|b| b.is_ascii_digit()).count();
+    let n = std::str::from_utf8(&buf[..len]).ok()?.parse().ok()?;
+    Some((n, &buf[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYNC_SET: &[u8] = b"\x1b[?2026;1$y";
+
+    fn report(mode: u32, private: bool, state: ModeState) -> ModeReport {
+        ModeReport { mode, private, state }
+    }
+
+    #[test]
+    fn states() {
+        for (ps, state, supported) in [
+            ("0", ModeState::NotRecognised, false),
+            ("1", ModeState::Set, true),
+            ("2", ModeState::Reset, true),
+            ("3", ModeState::PermanentlySet, true),
+            ("4", ModeState::PermanentlyReset, false),
+            ("5", ModeState::NotRecognised, false),
+        ] {
+            let buf = format!("\x1b[?2026;{}$y", ps);
+            let expected = report(2026, true, state);
+            assert_eq!(ModeReport::parse(buf.as_bytes()), Some((expected, 11)));
+            assert_eq!(state.is_supported(), supported);
+        }
+    }
+
+    #[test]
+    fn other_modes() {
+        assert_eq!(
+            ModeReport::parse(b"\x1b[?1049;2$y"),
+            Some((report(1049, true, ModeState::Reset), 11))
+        );
+        assert_eq!(
+            ModeReport::parse(b"\x1b[?7;3$y"),
+            Some((report(7, true, ModeState::PermanentlySet), 8))
+        );
+
+        /*
+         * ANSI modes are reported without the "?".
+         */
+        assert_eq!(
+            ModeReport::parse(b"\x1b[4;2$y"),
+            Some((report(4, false, ModeState::Reset), 7))
+        );
+    }
+
+    #[test]
+    fn trailing_input() {
+        let mut buf = SYNC_SET.to_vec();
+        buf.extend_from_slice(b"\x1b[Ax");
+        assert_eq!(
+            ModeReport::parse(&buf),
+            Some((report(2026, true, ModeState::Set), SYNC_SET.len()))
+        );
+        assert_eq!(ModeReport::parse(&buf[1..]), None);
+        assert_eq!(ModeReport::parse(&buf[SYNC_SET.len()..]), None);
+    }
+
+    #[test]
+    fn partial() {
+        for n in 0..SYNC_SET.len() {
+            assert_eq!(ModeReport::parse(&SYNC_SET[..n]), None);
+            assert_eq!(ModeReport::find(&SYNC_SET[..n]), None);
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        for buf in [
+            &b"\x1b[?2026$y"[..],
+            b"\x1b[?;1$y",
+            b"\x1b[?2026;$y",
+            b"\x1b[?2026;1y",
+            b"\x1b[?2026;1$p",
+            b"\x1b[?2026:1$y",
+            b"\x1b[??2026;1$y",
+            b"\x1b[?99999999999;1$y",
+            b"\x1bO?2026;1$y",
+        ] {
+            assert_eq!(ModeReport::parse(buf), None, "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn find_among_other_input() {
+        let mut buf = b"ab\x1b[A\x1b".to_vec();
+        buf.extend_from_slice(SYNC_SET);
+        buf.extend_from_slice(b"c\x1b[?1049;2$y");
+        let found = ModeReport::find(&buf);
+        assert_eq!(found, Some((report(2026, true, ModeState::Set), 6..17)));
+        assert_eq!(&buf[6..17], SYNC_SET);
+
+        /*
+         * Once the first report is removed, the next is found.
+         */
+        buf.drain(6..17);
+        assert_eq!(
+            ModeReport::find(&buf),
+            Some((report(1049, true, ModeState::Reset), 7..18))
+        );
+
+        assert_eq!(ModeReport::find(b""), None);
+        assert_eq!(ModeReport::find(b"abc\x1b[A\x1b[B"), None);
+    }
+
+    #[test]
+    fn find_partial_report() {
+        /*
+         * Nothing is found until the rest of the report arrives.
+         */
+        let mut buf = b"x".to_vec();
+        for &b in SYNC_SET.iter() {
+            assert_eq!(ModeReport::find(&buf), None);
+            buf.push(b);
+        }
+        assert_eq!(
+            ModeReport::find(&buf),
+            Some((report(2026, true, ModeState::Set), 1..12))
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use super::region::Point;
+
+/*
+ * Ways to move the cursor to the target row.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Vertical {
+    Stay,
+    Down(usize),
+    Up(usize),
+    /**
+     * Line feeds are cheap for short distances, but if the terminal is
+     * translating NL to CR-NL (e.g., because OPOST is still set) the cursor
+     * also returns to the first column, so the column is no longer known.
+     */
+    LineFeed(usize),
+    Row(usize),
+}
+
+/*
+ * Ways to move the cursor to the target column, once in the right row.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Horizontal {
+    Stay,
+    Right(usize),
+    Left(usize),
+    Backspace(usize),
+    /**
+     * Draw the characters already on the screen between the cursor and the
+     * target again, which is often shorter than a control sequence.
+     */
+    Reemit,
+    Return,
+    ReturnRight(usize),
+    Column(usize),
+}
+
+fn digits(mut n: usize) -> usize {
+    let mut d = 1;
+    while n >= 10 {
+        n /= 10;
+        d += 1;
+    }
+    d
+}
+
+/**
+ * The length of a control sequence with a single numeric parameter, which may
+ * be omitted when it has the default value of 1.
+ */
+fn csi_len(n: usize) -> usize {
+    if n == 1 {
+        3
+    } else {
+        3 + digits(n)
+    }
+}
+
+fn write_csi(out: &mut Vec<u8>, n: usize, fin: u8) {
+    out.extend_from_slice(b"\x1b[");
+    if n != 1 {
+        out.extend_from_slice(n.to_string().as_bytes());
+    }
+    out.push(fin);
+}
+
+impl Vertical {
+    fn cost(self) -> usize {
+        match self {
+            Vertical::Stay => 0,
+            Vertical::Down(n) | Vertical::Up(n) => csi_len(n),
+            Vertical::LineFeed(n) => n,
+            Vertical::Row(y) => csi_len(y + 1),
+        }
+    }
+
+    fn write(self, out: &mut Vec<u8>) {
+        match self {
+            Vertical::Stay => (),
+            Vertical::Down(n) => write_csi(out, n, b'B'),
+            Vertical::Up(n) => write_csi(out, n, b'A'),
+            Vertical::LineFeed(n) => out.resize(out.len() + n, b'\n'),
+            Vertical::Row(y) => write_csi(out, y + 1, b'd'),
+        }
+    }
+}
+
+impl Horizontal {
+    fn cost(self, reemit: &[u8]) -> usize {
+        match self {
+            Horizontal::Stay => 0,
+            Horizontal::Right(n) | Horizontal::Left(n) => csi_len(n),
+            Horizontal::Backspace(n) => n,
+            Horizontal::Reemit => reemit.len(),
+            Horizontal::Return => 1,
+            Horizontal::ReturnRight(n) => 1 + csi_len(n),
+            Horizontal::Column(x) => csi_len(x + 1),
+        }
+    }
+
+    fn write(self, out: &mut Vec<u8>, reemit: &[u8]) {
+        match self {
+            Horizontal::Stay => (),
+            Horizontal::Right(n) => write_csi(out, n, b'C'),
+            Horizontal::Left(n) => write_csi(out, n, b'D'),
+            Horizontal::Backspace(n) => out.resize(out.len() + n, b'\x08'),
+            Horizontal::Reemit => out.extend_from_slice(reemit),
+            Horizontal::Return => out.push(b'\r'),
+            Horizontal::ReturnRight(n) => {
+                out.push(b'\r');
+                write_csi(out, n, b'C');
+            }
+            Horizontal::Column(x) => write_csi(out, x + 1, b'G'),
+        }
+    }
+}
+
+/**
+ * The length of the sequence that moves the cursor to an absolute position.
+ */
+fn absolute_len(to: Point) -> usize {
+    match (to.y, to.x) {
+        (0, 0) => 3,
+        (y, 0) => 3 + digits(y + 1),
+        (y, x) => 4 + digits(y + 1) + digits(x + 1),
+    }
+}
+
+fn write_absolute(out: &mut Vec<u8>, to: Point) {
+    match (to.y, to.x) {
+        (0, 0) => out.extend_from_slice(b"\x1b[f"),
+        (y, 0) => out.extend_from_slice(format!("\x1b[{}f", y + 1).as_bytes()),
+        (y, x) => out
+            .extend_from_slice(format!("\x1b[{};{}f", y + 1, x + 1).as_bytes()),
+    }
+}
+
+/**
+ * Emit the shortest sequence we can find that moves the cursor from one cell
+ * to another.  CUU, CUD, CUF, CUB, and CUP, along with the BS, LF, and CR
+ * control characters, are understood by every terminal since the VT100, and
+ * Draw has always relied on CHA; they are used freely.  VPA came later, and is
+ * used only if "vpa" is set.  If the starting position is not known, the cursor is moved to
+ * an absolute position.  A starting column equal to the width of the screen
+ * means that the last column has just been written and the terminal may be
+ * waiting to wrap, in which case terminals disagree about where relative
+ * horizontal motion should start, so we move to an absolute column.
+ *
+ * If "reemit" is provided, it contains the characters that are on the screen
+ * between the cursor and the target in the same row, which may be written
+ * again to move the cursor.  The caller must ensure that they would be drawn
+ * in the current format.
+ */
+pub(crate) fn move_cursor(
+    out: &mut Vec<u8>,
+    from: Option<Point>,
+    to: Point,
+    width: usize,
+    vpa: bool,
+    reemit: Option<&[u8]>,
+) {
+    let Some(from) = from else {
+        write_absolute(out, to);
+        return;
+    };
+
+    let row = vpa.then_some(Vertical::Row(to.y));
+    let verticals = if to.y == from.y {
+        [Some(Vertical::Stay), None, None]
+    } else if to.y > from.y {
+        let n = to.y - from.y;
+        [Some(Vertical::Down(n)), Some(Vertical::LineFeed(n)), row]
+    } else {
+        [Some(Vertical::Up(from.y - to.y)), row, None]
+    };
+
+    let mut best: Option<(usize, Vertical, Horizontal)> = None;
+    for v in verticals.into_iter().flatten() {
+        let col = match v {
+            _ if from.x >= width => None,
+            Vertical::LineFeed(_) => None,
+            _ => Some(from.x),
+        };
+
+        let mut horizontals = vec![Horizontal::Column(to.x)];
+        horizontals.push(if to.x == 0 {
+            Horizontal::Return
+        } else {
+            Horizontal::ReturnRight(to.x)
+        });
+        match col {
+            Some(x) if x == to.x => horizontals.push(Horizontal::Stay),
+            Some(x) if x < to.x => {
+                horizontals.push(Horizontal::Right(to.x - x));
+                if reemit.is_some() && v == Vertical::Stay {
+                    horizontals.push(Horizontal::Reemit);
+                }
+            }
+            Some(x) => {
+                horizontals.push(Horizontal::Left(x - to.x));
+                horizontals.push(Horizontal::Backspace(x - to.x));
+            }
+            None => (),
+        }
+
+        for h in horizontals {
+            let cost = v.cost() + h.cost(reemit.unwrap_or_default());
+            if best.map(|(c, _, _)| cost < c).unwrap_or(true) {
+                best = Some((cost, v, h));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, v, h)) if cost < absolute_len(to) => {
+            v.write(out);
+            h.write(out, reemit.unwrap_or_default());
+        }
+        _ => write_absolute(out, to),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 80;
+
+    fn plan(from: Option<(usize, usize)>, to: (usize, usize)) -> Vec<u8> {
+        plan_with(from, to, true, None)
+    }
+
+    fn plan_reemit(
+        from: Option<(usize, usize)>,
+        to: (usize, usize),
+        reemit: Option<&[u8]>,
+    ) -> Vec<u8> {
+        plan_with(from, to, true, reemit)
+    }
+
+    fn plan_with(
+        from: Option<(usize, usize)>,
+        to: (usize, usize),
+        vpa: bool,
+        reemit: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        move_cursor(
+            &mut out,
+            from.map(|(x, y)| Point::new(x, y)),
+            Point::new(to.0, to.1),
+            WIDTH,
+            vpa,
+            reemit,
+        );
+        out
+    }
+
+    #[test]
+    fn unknown_position_is_absolute() {
+        assert_eq!(plan(None, (9, 4)), b"\x1b[5;10f");
+        assert_eq!(plan(None, (0, 4)), b"\x1b[5f");
+        assert_eq!(plan(None, (0, 0)), b"\x1b[f");
+    }
+
+    #[test]
+    fn no_motion() {
+        assert_eq!(plan(Some((7, 3)), (7, 3)).len(), 0);
+    }
+
+    #[test]
+    fn skip_right() {
+        assert_eq!(plan(Some((10, 2)), (15, 2)), b"\x1b[5C");
+        assert_eq!(plan(Some((10, 2)), (11, 2)), b"\x1b[C");
+    }
+
+    #[test]
+    fn reemit_short_skips() {
+        assert_eq!(plan_reemit(Some((10, 2)), (11, 2), Some(b"a")), b"a");
+        assert_eq!(plan_reemit(Some((10, 2)), (13, 2), Some(b"abc")), b"abc");
+
+        /*
+         * Beyond three cells, a control sequence is no longer than drawing
+         * the characters again.
+         */
+        let out = plan_reemit(Some((10, 2)), (16, 2), Some(b"abcdef"));
+        assert_eq!(out, b"\x1b[6C");
+    }
+
+    #[test]
+    fn reemit_only_in_same_row() {
+        let out = plan_reemit(Some((10, 2)), (11, 3), Some(b"a"));
+        assert_eq!(out, b"\x1b[B\x1b[C");
+    }
+
+    #[test]
+    fn back_up() {
+        assert_eq!(plan(Some((10, 2)), (9, 2)), b"\x08");
+        assert_eq!(plan(Some((10, 2)), (7, 2)), b"\x08\x08\x08");
+        assert_eq!(plan(Some((40, 2)), (35, 2)), b"\x1b[5D");
+    }
+
+    #[test]
+    fn start_of_line() {
+        assert_eq!(plan(Some((40, 2)), (0, 2)), b"\r");
+        assert_eq!(plan(Some((40, 2)), (0, 3)), b"\n\r");
+        assert_eq!(plan(Some((40, 2)), (2, 2)), b"\x1b[3G");
+    }
+
+    #[test]
+    fn vertical() {
+        assert_eq!(plan(Some((5, 10)), (5, 9)), b"\x1b[A");
+        assert_eq!(plan(Some((5, 10)), (5, 11)), b"\x1b[B");
+        assert_eq!(plan(Some((5, 10)), (5, 20)), b"\x1b[10B");
+        assert_eq!(plan(Some((5, 10)), (5, 0)), b"\x1b[d");
+
+        /*
+         * When relative motion is no shorter, a single absolute move is used.
+         */
+        assert_eq!(plan(Some((5, 10)), (6, 0)), b"\x1b[1;7f");
+    }
+
+    #[test]
+    fn vertical_without_vpa() {
+        let plan = |from, to| plan_with(Some(from), to, false, None);
+        assert_eq!(plan((5, 10), (5, 0)), b"\x1b[10A");
+        assert_eq!(plan((5, 10), (5, 9)), b"\x1b[A");
+        assert_eq!(plan((5, 2), (5, 40)), b"\x1b[38B");
+        assert_eq!(plan((5, 40), (5, 2)), b"\x1b[38A");
+        assert_eq!(plan((5, 100), (5, 0)), b"\x1b[1;6f");
+        assert_eq!(plan_with(Some((5, 100)), (5, 0), true, None), b"\x1b[d");
+    }
+
+    #[test]
+    fn long_jumps_are_absolute() {
+        assert_eq!(plan(Some((0, 0)), (70, 40)), b"\x1b[41;71f");
+        assert_eq!(plan(Some((70, 40)), (3, 2)), b"\x1b[3;4f");
+    }
+
+    #[test]
+    fn pending_wrap_uses_absolute_column() {
+        assert_eq!(plan(Some((WIDTH, 5)), (3, 5)), b"\x1b[4G");
+        assert_eq!(plan(Some((WIDTH, 5)), (0, 5)), b"\r");
+        assert_eq!(plan(Some((WIDTH, 5)), (0, 6)), b"\n\r");
+    }
+}
@@ -1,19 +1,147 @@
-#[derive(Clone, Copy, PartialEq)]
+use super::grapheme::{cluster_width, Grapheme};
+use super::view::View;
+use std::sync::atomic::{AtomicU64, Ordering};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Point {
-    x: usize,
-    y: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Point {
+    pub fn new(x: usize, y: usize) -> Point {
+        Point { x, y }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum CursorShape {
+    /**
+     * Whatever shape the user has configured for their terminal.
+     */
+    #[default]
+    Default,
+    Block,
+    Underline,
+    Bar,
+}
+
+/**
+ * The appearance of the hardware cursor.  Not every terminal is able to change
+ * the shape or colour of the cursor; those that cannot will ignore the
+ * request.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    /**
+     * Whether the cursor blinks.  This is ignored for the default shape.
+     */
+    pub blink: bool,
+    pub colour: Colour,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        CursorStyle {
+            shape: CursorShape::Default,
+            blink: true,
+            colour: Colour::Default,
+        }
+    }
+}
+
+/**
+ * A rectangular area, in cells, with its top-left corner at (x, y).
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /**
+     * The part of this rectangle that lies within an area of the given size,
+     * anchored at the origin.
+     */
+    pub fn clip(&self, width: usize, height: usize) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Rect {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/**
+ * The horizontal placement of text within a wider space.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Centre,
+    Right,
 }
 
 pub struct Region {
     width: usize,
     height: usize,
-    rows: Vec<Vec<Cell>>,
+    /**
+     * The cells of each row in turn.
+     */
+    cells: Vec<Cell>,
+    /**
+     * A number that is unique to this region.
+     */
+    id: u64,
+    /**
+     * Bumped whenever a cell is changed.  Each row records the generation at
+     * which it was last changed; see RowStamp.
+     */
+    generation: u64,
+    stamps: Vec<u64>,
     cursor: Option<Point>,
+    cursor_style: CursorStyle,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+static NEXT_REGION_ID: AtomicU64 = AtomicU64::new(1);
+
+/**
+ * Identifies the contents of a row of a region at some point in time.  If the
+ * stamp of a row is unchanged, so are its contents; this allows Draw to skip
+ * rows that have not been touched since the last frame without comparing each
+ * cell.  The default stamp matches no row.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) struct RowStamp {
+    region: u64,
+    generation: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Colour {
     Default,
+    /**
+     * An index into the 16 colour palette: 0 through 7 are the standard
+     * colours and 8 through 15 their bright variants.  Any other value is
+     * treated as the default colour.
+     */
     C16(u8),
     C256(u8),
     RGB(u8, u8, u8),
@@ -29,56 +157,149 @@ impl Colour {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+/**
+ * The style of line drawn under text.  Styles other than Single are an
+ * extension that not every terminal supports; those that do not will
+ * generally fall back to a single underline.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Underline {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Format {
     pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: Underline,
+    pub blink: bool,
     pub reverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+    pub overline: bool,
     pub fg: Colour,
     pub bg: Colour,
+    pub underline_colour: Colour,
 }
 
 impl Default for Format {
     fn default() -> Self {
         Format {
             bold: false,
+            dim: false,
+            italic: false,
+            underline: Underline::None,
+            blink: false,
             reverse: false,
+            hidden: false,
+            strikethrough: false,
+            overline: false,
             fg: Colour::Default,
             bg: Colour::Default,
+            underline_colour: Colour::Default,
         }
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Hash)]
 pub struct Cell {
-    contents: char,
+    contents: Grapheme,
     width: usize,
     format: Format,
 }
 
 impl Default for Cell {
     fn default() -> Self {
-        Cell { contents: ' ', width: 1, format: Format::default() }
+        Cell {
+            contents: Grapheme::default(),
+            width: 1,
+            format: Format::default(),
+        }
     }
 }
 
 impl Cell {
+    /**
+     * Store a character in this cell, returning the number of columns it
+     * occupies.  A zero-width character (e.g., a combining mark) is attached
+     * to the existing contents of the cell rather than replacing them.
+     * Control characters are discarded.
+     */
     pub fn chr(&mut self, ch: char) -> usize {
-        /*
-         * XXX deal with character width.
-         */
-        self.contents = ch;
-        self.width = 1;
+        self.grapheme(ch.encode_utf8(&mut [0u8; 4]))
+    }
+
+    /**
+     * Store a single extended grapheme cluster in this cell, returning the
+     * number of columns it occupies.  As with chr(), a cluster of zero width
+     * is attached to the existing contents of the cell.
+     */
+    pub fn grapheme(&mut self, g: &str) -> usize {
+        if g.is_empty() || g.chars().any(|ch| ch.is_control()) {
+            return 0;
+        }
+
+        let width = cluster_width(g);
+        if width == 0 {
+            if !self.is_continuation() {
+                self.contents.push_str(g);
+            }
+            return 0;
+        }
+
+        self.contents = Grapheme::from_str(g);
+        self.width = width;
         self.width
     }
 
-    pub fn contents(&self) -> char {
-        self.contents
+    /**
+     * The grapheme cluster stored in this cell, including any attached
+     * combining marks.
+     */
+    pub fn contents(&self) -> &str {
+        self.contents.as_str()
     }
 
+    /**
+     * The number of columns occupied by the contents of this cell.  This is
+     * zero for a continuation cell.
+     */
     pub fn width(&self) -> usize {
         self.width
     }
 
+    /**
+     * Is this the trailing column of a wide character that begins in the cell
+     * to the left?
+     */
+    pub fn is_continuation(&self) -> bool {
+        self.width == 0
+    }
+
+    /**
+     * Turn this cell into the trailing column of a wide character.
+     */
+    pub(crate) fn continuation(&mut self, f: &Format) {
+        self.contents = Grapheme::default();
+        self.width = 0;
+        self.format = *f;
+    }
+
+    /**
+     * Replace the contents of this cell with a blank, keeping the format.
+     */
+    fn blank(&mut self) {
+        self.contents = Grapheme::default();
+        self.width = 1;
+    }
+
     pub fn clear(&mut self) {
         self.set_from(&Cell::default());
     }
@@ -91,18 +312,70 @@ impl Cell {
         self.format.bold = true;
     }
 
+    pub fn dim(&mut self) {
+        self.format.dim = true;
+    }
+
+    pub fn italic(&mut self) {
+        self.format.italic = true;
+    }
+
+    pub fn underline(&mut self, style: Underline) {
+        self.format.underline = style;
+    }
+
+    pub fn underline_colour(&mut self, c: Colour) {
+        self.format.underline_colour = c;
+    }
+
+    pub fn blink(&mut self) {
+        self.format.blink = true;
+    }
+
     pub fn reverse(&mut self) {
         self.format.reverse = true;
     }
 
+    pub fn hidden(&mut self) {
+        self.format.hidden = true;
+    }
+
+    pub fn strikethrough(&mut self) {
+        self.format.strikethrough = true;
+    }
+
+    pub fn overline(&mut self) {
+        self.format.overline = true;
+    }
+
     pub fn format(&self) -> &Format {
         &self.format
     }
 
     pub fn set_format(&mut self, f: &Format) {
-        let Format { bold, reverse, fg, bg } = f;
+        let Format {
+            bold,
+            dim,
+            italic,
+            underline,
+            blink,
+            reverse,
+            hidden,
+            strikethrough,
+            overline,
+            fg,
+            bg,
+            underline_colour,
+        } = f;
         self.format.bold = *bold;
+        self.format.dim = *dim;
+        self.format.italic = *italic;
+        self.format.underline = *underline;
+        self.format.blink = *blink;
         self.format.reverse = *reverse;
+        self.format.hidden = *hidden;
+        self.format.strikethrough = *strikethrough;
+        self.format.overline = *overline;
         match bg {
             Colour::UseExisting => (),
             other => self.format.bg = *other,
@@ -111,36 +384,111 @@ impl Cell {
             Colour::UseExisting => (),
             other => self.format.fg = *other,
         }
+        match underline_colour {
+            Colour::UseExisting => (),
+            other => self.format.underline_colour = *other,
+        }
     }
 
     pub fn set_from(&mut self, other: &Cell) {
-        self.contents = other.contents;
+        self.contents.clone_from(&other.contents);
         self.width = other.width;
         self.format = other.format;
     }
+
+    /**
+     * Like set_from(), except that any colours in the other cell that are
+     * Colour::UseExisting leave the colours in this cell as they are.
+     */
+    pub fn composite_from(&mut self, other: &Cell) {
+        self.contents.clone_from(&other.contents);
+        self.width = other.width;
+        self.set_format(&other.format);
+    }
+
+    /**
+     * Make this cell blank and see-through: when composited onto another
+     * region, it will leave the destination cell untouched.
+     */
+    pub fn clear_transparent(&mut self) {
+        self.clear();
+        self.format.fg = Colour::UseExisting;
+        self.format.bg = Colour::UseExisting;
+        self.format.underline_colour = Colour::UseExisting;
+    }
+
+    /**
+     * A cell is transparent if it has no contents and its background is
+     * Colour::UseExisting.
+     */
+    pub fn is_transparent(&self) -> bool {
+        self.width == 1
+            && self.contents() == " "
+            && self.format.bg == Colour::UseExisting
+    }
 }
 
 impl Region {
     pub fn new(width: usize, height: usize) -> Region {
-        let mut rows = Vec::with_capacity(height);
+        let mut cells = Vec::with_capacity(width * height);
+        cells.resize_with(width * height, Cell::default);
 
-        while rows.len() < height {
-            let mut col = Vec::with_capacity(width);
-            while col.len() < width {
-                col.push(Cell::default());
-            }
+        Region {
+            width,
+            height,
+            cells,
+            id: NEXT_REGION_ID.fetch_add(1, Ordering::Relaxed),
+            generation: 0,
+            stamps: vec![0; height],
+            cursor: None,
+            cursor_style: CursorStyle::default(),
+        }
+    }
+
+    /**
+     * Change the size of the region.  The contents of any cells that remain
+     * within the new bounds are preserved; new cells are blank.
+     */
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let mut old = std::mem::take(&mut self.cells).into_iter();
+        let mut cells = Vec::with_capacity(width * height);
 
-            rows.push(col);
+        for _ in 0..self.height.min(height) {
+            let mut row: Vec<Cell> = old.by_ref().take(self.width).collect();
+            row.truncate(width);
+            row.resize_with(width, Cell::default);
+
+            /*
+             * If a wide character has been cut in half by the new right
+             * edge, replace what remains with a blank.
+             */
+            if let Some(last) = row.last_mut() {
+                if last.width() > 1 {
+                    last.blank();
+                }
+            }
+            cells.extend(row);
         }
+        cells.resize_with(width * height, Cell::default);
+
+        self.cells = cells;
+        self.width = width;
+        self.height = height;
+        self.stamps.resize(height, 0);
+        self.touch_rows(0, height);
 
-        Region { width, height, rows, cursor: None }
+        if let Some(p) = self.cursor {
+            if p.x >= width || p.y >= height {
+                self.cursor = None;
+            }
+        }
     }
 
     pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
         if x >= self.width || y >= self.height {
             None
         } else {
-            Some(&self.rows[y][x])
+            Some(&self.cells[y * self.width + x])
         }
     }
 
@@ -148,57 +496,207 @@ impl Region {
         if x >= self.width || y >= self.height {
             None
         } else {
-            Some(&mut self.rows[y][x])
+            Some(&mut self.row_mut(y)[x])
         }
     }
 
     pub fn chr(&mut self, x: usize, y: usize, ch: char) -> usize {
-        if let Some(c) = self.cell_mut(x, y) {
-            c.reset();
-            c.chr(ch)
-        } else {
+        let mut buf = [0u8; 4];
+        self.put(0, self.width, x, y, ch.encode_utf8(&mut buf), None)
+    }
+
+    pub fn chrf(&mut self, x: usize, y: usize, ch: char, f: &Format) -> usize {
+        let mut buf = [0u8; 4];
+        self.put(0, self.width, x, y, ch.encode_utf8(&mut buf), Some(f))
+    }
+
+    /**
+     * Write a single grapheme cluster at (x, y), confined to the columns from
+     * "left" up to but not including "right".
+     */
+    pub(crate) fn put(
+        &mut self,
+        left: usize,
+        right: usize,
+        x: usize,
+        y: usize,
+        g: &str,
+        f: Option<&Format>,
+    ) -> usize {
+        if y >= self.height {
             /*
              * Don't write off the edge of the screen.
              */
-            0
+            return 0;
         }
-    }
 
-    pub fn chrf(&mut self, x: usize, y: usize, ch: char, f: &Format) -> usize {
-        if let Some(c) = self.cell_mut(x, y) {
-            c.set_format(f);
-            c.chr(ch)
-        } else {
+        let width = cluster_width(g);
+        if width == 0 {
+            /*
+             * A zero-width cluster does not consume a column of its own.
+             * Attach it to whatever was written in the column to the left,
+             * which may be the trailing edge of a wide character.
+             */
+            if x > left && x <= right {
+                let mut px = x - 1;
+                let row = self.row_mut(y);
+                if px > left && row[px].is_continuation() {
+                    px -= 1;
+                }
+                row[px].grapheme(g);
+            }
+            return 0;
+        }
+
+        if x >= right {
             /*
              * Don't write off the edge of the screen.
              */
-            0
+            return 0;
         }
+
+        /*
+         * If we are about to overwrite one half of an existing wide character,
+         * the other half must be blanked out so that no orphaned fragment is
+         * left behind.
+         */
+        self.break_wide(x, y);
+
+        let g = if width > 1 && x + width > right {
+            /*
+             * A wide character cannot be split across the right edge of the
+             * screen.  Leave a blank in its place, as a terminal would.
+             */
+            " "
+        } else {
+            for cx in (x + 1)..(x + width) {
+                self.break_wide(cx, y);
+            }
+            g
+        };
+
+        let row = self.row_mut(y);
+        let c = &mut row[x];
+        match f {
+            Some(f) => c.set_format(f),
+            None => c.reset(),
+        }
+        let width = c.grapheme(g);
+
+        if width > 1 {
+            let f = *c.format();
+            row[(x + 1)..(x + width)]
+                .iter_mut()
+                .for_each(|c| c.continuation(&f));
+        }
+
+        width
     }
 
-    pub fn str(&mut self, mut x: usize, y: usize, s: &str) -> usize {
-        let ox = x;
+    pub(crate) fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    /**
+     * Borrow a row in order to change it, which updates its stamp.
+     */
+    fn row_mut(&mut self, y: usize) -> &mut [Cell] {
+        self.touch_rows(y, y + 1);
+        &mut self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    fn touch_rows(&mut self, top: usize, bottom: usize) {
+        self.generation += 1;
+        self.stamps[top..bottom].fill(self.generation);
+    }
+
+    pub(crate) fn row_stamp(&self, y: usize) -> RowStamp {
+        RowStamp { region: self.id, generation: self.stamps[y] }
+    }
+
+    /**
+     * Move the rows from "top" to "bottom" inclusive up (or down) by "n" rows,
+     * as a terminal does when scrolling a region.  Rows that are uncovered are
+     * left blank.
+     */
+    pub(crate) fn scroll(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        n: usize,
+        up: bool,
+    ) {
+        self.touch_rows(top, bottom + 1);
+        let w = self.width;
+        let cells = &mut self.cells[top * w..(bottom + 1) * w];
+        let n = n.min(bottom + 1 - top) * w;
 
-        /* XXX graphemes? */
-        for ch in s.chars() {
-            x += self.chr(x, y, ch);
+        let vacated = if up {
+            cells.rotate_left(n);
+            cells.len() - n..cells.len()
+        } else {
+            cells.rotate_right(n);
+            0..n
+        };
+        cells[vacated].fill_with(Cell::default);
+    }
+
+    /**
+     * If the cell at (x, y) is part of a wide character, replace the whole of
+     * that character with blanks, keeping the format of each cell.
+     */
+    fn break_wide(&mut self, x: usize, y: usize) {
+        let width = self.width;
+        if self.row(y)[x].width() == 1 {
+            return;
         }
+        let row = self.row_mut(y);
 
-        x - ox
+        let mut lx = x;
+        while row[lx].is_continuation() && lx > 0 {
+            lx -= 1;
+        }
+
+        let mut tx = x + 1;
+        while tx < width && row[tx].is_continuation() {
+            tx += 1;
+        }
+
+        row[lx..tx].iter_mut().for_each(|c| c.blank());
+    }
+
+    pub fn str(&mut self, x: usize, y: usize, s: &str) -> usize {
+        self.text(0, self.width, x, y, s, None)
+    }
+
+    pub fn strf(&mut self, x: usize, y: usize, s: &str, f: &Format) -> usize {
+        self.text(0, self.width, x, y, s, Some(f))
     }
 
-    pub fn strf(
+    /**
+     * Write a string at (x, y), confined to the columns from "left" up to but
+     * not including "right".
+     */
+    pub(crate) fn text(
         &mut self,
+        left: usize,
+        right: usize,
         mut x: usize,
         y: usize,
         s: &str,
-        f: &Format,
+        f: Option<&Format>,
     ) -> usize {
         let ox = x;
 
-        /* XXX graphemes? */
-        for ch in s.chars() {
-            x += self.chrf(x, y, ch, f);
+        for g in s.graphemes(true) {
+            let w = self.put(left, right, x, y, g, f);
+            if w == 0 && cluster_width(g) > 0 {
+                /*
+                 * We have run off the edge of the screen.
+                 */
+                break;
+            }
+            x += w;
         }
 
         x - ox
@@ -213,11 +711,205 @@ impl Region {
     }
 
     pub fn clear(&mut self) {
-        for y in 0..self.height {
-            for x in 0..self.width {
-                self.rows[y][x].clear();
+        self.touch_rows(0, self.height);
+        self.cells.iter_mut().for_each(|c| c.clear());
+    }
+
+    /**
+     * Call a function for each cell within a rectangle, one row at a time.
+     * Any wide character that straddles the edge of the rectangle is broken
+     * up first, so that no half of it is left behind.
+     */
+    fn each_in_rect<F: FnMut(&mut Cell)>(&mut self, r: Rect, mut func: F) {
+        let r = r.clip(self.width, self.height);
+        if r.is_empty() {
+            return;
+        }
+
+        for y in r.y..(r.y + r.height) {
+            self.break_wide(r.x, y);
+            self.break_wide(r.x + r.width - 1, y);
+
+            self.row_mut(y)[r.x..(r.x + r.width)]
+                .iter_mut()
+                .for_each(&mut func);
+        }
+    }
+
+    /**
+     * Fill a rectangle with a character in the given format.  A character
+     * that is not exactly one column wide is written with chrf() instead,
+     * and is confined to the rectangle.
+     */
+    pub fn fill_rect(&mut self, r: Rect, ch: char, f: &Format) {
+        let mut buf = [0u8; 4];
+        let g = ch.encode_utf8(&mut buf);
+
+        if cluster_width(g) != 1 {
+            let r = r.clip(self.width, self.height);
+            for y in r.y..(r.y + r.height) {
+                let mut x = r.x;
+                while x < r.x + r.width {
+                    x += self.put(r.x, r.x + r.width, x, y, g, Some(f)).max(1);
+                }
             }
+            return;
         }
+
+        let contents = Grapheme::from_char(ch);
+        self.each_in_rect(r, |c| {
+            c.contents.clone_from(&contents);
+            c.width = 1;
+            c.set_format(f);
+        });
+    }
+
+    /**
+     * Clear the cells within a rectangle, as for Cell::clear().
+     */
+    pub fn clear_rect(&mut self, r: Rect) {
+        self.each_in_rect(r, |c| c.clear());
+    }
+
+    /**
+     * Change the format of the cells within a rectangle without altering
+     * their contents.  As with Cell::set_format(), colours that are
+     * Colour::UseExisting are left as they are.  A wide character that
+     * straddles the edge of the rectangle cannot be given two formats, so it
+     * is broken up as for clear_rect().
+     */
+    pub fn paint_rect(&mut self, r: Rect, f: &Format) {
+        self.each_in_rect(r, |c| c.set_format(f));
+    }
+
+    /**
+     * Create a region in which every cell is transparent; see
+     * Cell::clear_transparent().
+     */
+    pub fn new_transparent(width: usize, height: usize) -> Region {
+        let mut r = Region::new(width, height);
+        r.clear_transparent();
+        r
+    }
+
+    pub fn clear_transparent(&mut self) {
+        self.touch_rows(0, self.height);
+        self.cells.iter_mut().for_each(|c| c.clear_transparent());
+    }
+
+    /**
+     * Copy the cells within a rectangle of another region into this region,
+     * with the top-left corner of the rectangle at (dst_x, dst_y).  Anything
+     * that would fall outside either region is clipped.
+     */
+    pub fn blit(
+        &mut self,
+        src: &Region,
+        src_rect: Rect,
+        dst_x: usize,
+        dst_y: usize,
+    ) {
+        self.blit_common(src, src_rect, dst_x, dst_y, false);
+    }
+
+    /**
+     * Like blit(), except that transparent cells in the source leave the
+     * destination untouched, and Colour::UseExisting in the format of a
+     * source cell keeps the colour from the destination.  This allows popups
+     * and overlays to be layered over an existing view.
+     */
+    pub fn composite(
+        &mut self,
+        src: &Region,
+        src_rect: Rect,
+        dst_x: usize,
+        dst_y: usize,
+    ) {
+        self.blit_common(src, src_rect, dst_x, dst_y, true);
+    }
+
+    fn blit_common(
+        &mut self,
+        src: &Region,
+        src_rect: Rect,
+        dst_x: usize,
+        dst_y: usize,
+        composite: bool,
+    ) {
+        let sr = src_rect.clip(src.width, src.height);
+        let dr = Rect::new(dst_x, dst_y, sr.width, sr.height)
+            .clip(self.width, self.height);
+        let mut blank = Cell::default();
+
+        for row in 0..dr.height {
+            let srow = src.row(sr.y + row);
+            let y = dr.y + row;
+
+            /*
+             * Determine which source cells will be written, and deal with any
+             * wide characters in the destination that are about to be cut in
+             * half before we start.
+             */
+            let opaque =
+                |i: usize| !composite || !srow[sr.x + i].is_transparent();
+            for i in (0..dr.width).filter(|&i| opaque(i)) {
+                self.break_wide(dr.x + i, y);
+            }
+
+            for i in (0..dr.width).filter(|&i| opaque(i)) {
+                let sc = &srow[sr.x + i];
+                let x = dr.x + i;
+
+                let sc = if (sc.is_continuation() && i == 0)
+                    || i + sc.width() > dr.width
+                {
+                    /*
+                     * The other half of this wide character lies outside the
+                     * area being copied, so put a blank in its place.
+                     */
+                    blank.set_from(sc);
+                    blank.blank();
+                    &blank
+                } else if sc.is_continuation() {
+                    /*
+                     * This was taken care of along with the leading column.
+                     */
+                    continue;
+                } else {
+                    sc
+                };
+
+                let drow = self.row_mut(y);
+                let dc = &mut drow[x];
+                if composite {
+                    dc.composite_from(sc);
+                } else {
+                    dc.set_from(sc);
+                }
+
+                let f = *dc.format();
+                let w = dc.width();
+                drow[(x + 1)..(x + w)]
+                    .iter_mut()
+                    .for_each(|c| c.continuation(&f));
+            }
+        }
+    }
+
+    /**
+     * Borrow a rectangular part of this region as a drawing surface with its
+     * own origin.  Drawing through the view is confined to the rectangle,
+     * which is itself clipped to the bounds of the region.
+     */
+    pub fn view_mut(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> View<'_> {
+        let rect = Rect::new(x, y, width, height).clip(self.width, self.height);
+        View::new(self, rect)
     }
 
     pub fn cursor(&self) -> Option<Point> {
@@ -227,4 +919,320 @@ impl Region {
     pub fn set_cursor(&mut self, curs: Option<Point>) {
         self.cursor = curs;
     }
+
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_style
+    }
+
+    pub fn set_cursor_style(&mut self, style: CursorStyle) {
+        self.cursor_style = style;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * The contents of a row as they would appear on the screen.
+     */
+    fn row(r: &Region, y: usize) -> String {
+        let mut s = String::new();
+        for x in 0..r.width() {
+            let c = r.cell(x, y).unwrap();
+            if !c.is_continuation() {
+                s.push_str(c.contents());
+            }
+        }
+        s
+    }
+
+    fn widths(r: &Region, y: usize) -> Vec<usize> {
+        (0..r.width()).map(|x| r.cell(x, y).unwrap().width()).collect()
+    }
+
+    #[test]
+    fn wide_character() {
+        let mut r = Region::new(4, 1);
+        assert_eq!(r.chr(1, 0, '中'), 2);
+        assert_eq!(row(&r, 0), " 中 ");
+        assert_eq!(widths(&r, 0), [1, 2, 0, 1]);
+        assert_eq!(r.str(0, 0, "a中"), 3);
+        assert_eq!(row(&r, 0), "a中 ");
+    }
+
+    #[test]
+    fn overwrite_left_half() {
+        let mut r = Region::new(4, 1);
+        r.str(0, 0, "中文");
+        r.chr(0, 0, 'a');
+        assert_eq!(row(&r, 0), "a 文");
+        assert_eq!(widths(&r, 0), [1, 1, 2, 0]);
+    }
+
+    #[test]
+    fn overwrite_right_half() {
+        let mut r = Region::new(4, 1);
+        r.str(0, 0, "中文");
+        r.chr(1, 0, 'a');
+        assert_eq!(row(&r, 0), " a文");
+        assert_eq!(widths(&r, 0), [1, 1, 2, 0]);
+
+        /*
+         * A wide character that straddles two others breaks both.
+         */
+        let mut r = Region::new(4, 1);
+        r.str(0, 0, "中文");
+        r.chr(1, 0, '字');
+        assert_eq!(row(&r, 0), " 字 ");
+        assert_eq!(widths(&r, 0), [1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn wide_character_at_last_column() {
+        /*
+         * A wide character that does not fit is replaced with a blank, and
+         * anything it would have overwritten is left alone.
+         */
+        let mut r = Region::new(3, 1);
+        r.str(0, 0, "abc");
+        assert_eq!(r.chr(2, 0, '中'), 1);
+        assert_eq!(row(&r, 0), "ab ");
+        assert_eq!(widths(&r, 0), [1, 1, 1]);
+
+        let mut r = Region::new(3, 1);
+        assert_eq!(r.str(0, 0, "ab中x"), 3);
+        assert_eq!(row(&r, 0), "ab ");
+        assert_eq!(r.chr(3, 0, 'x'), 0);
+    }
+
+    #[test]
+    fn combining_marks() {
+        let mut r = Region::new(4, 1);
+        assert_eq!(r.str(0, 0, "e\u{301}x"), 2);
+        assert_eq!(row(&r, 0), "e\u{301}x  ");
+        assert_eq!(r.cell(0, 0).unwrap().contents(), "e\u{301}");
+
+        /*
+         * A mark after a wide character attaches to its leading cell.
+         */
+        r.str(2, 0, "中\u{308}");
+        assert_eq!(row(&r, 0), "e\u{301}x中\u{308}");
+        assert_eq!(r.cell(3, 0).unwrap().contents(), " ");
+
+        /*
+         * A mark with nothing before it is dropped, and replacing a character
+         * removes its marks.
+         */
+        let mut r = Region::new(2, 1);
+        assert_eq!(r.chr(0, 0, '\u{301}'), 0);
+        assert_eq!(row(&r, 0), "  ");
+        r.str(0, 0, "e\u{301}");
+        r.chr(0, 0, 'f');
+        assert_eq!(row(&r, 0), "f ");
+    }
+
+    #[test]
+    fn grapheme_clusters() {
+        /*
+         * Emoji joined with ZWJ, and flags, occupy a single wide cell.
+         */
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let mut r = Region::new(6, 1);
+        assert_eq!(r.str(0, 0, family), 2);
+        assert_eq!(r.str(2, 0, "\u{1F1EC}\u{1F1E7}x"), 3);
+        assert_eq!(r.cell(0, 0).unwrap().contents(), family);
+        assert_eq!(widths(&r, 0), [2, 0, 2, 0, 1, 1]);
+        assert_eq!(row(&r, 0), format!("{}\u{1F1EC}\u{1F1E7}x ", family));
+
+        /*
+         * Overwriting half of a cluster blanks the whole of it.
+         */
+        r.chr(1, 0, 'a');
+        assert_eq!(row(&r, 0), " a\u{1F1EC}\u{1F1E7}x ");
+
+        /*
+         * Marks written separately join the cluster to their left, however
+         * long it grows.
+         */
+        let mut r = Region::new(2, 1);
+        r.chr(0, 0, 'e');
+        r.chr(1, 0, '\u{301}');
+        r.str(1, 0, "\u{323}\u{304}");
+        assert_eq!(r.cell(0, 0).unwrap().contents(), "e\u{301}\u{323}\u{304}");
+        assert_eq!(widths(&r, 0), [1, 1]);
+    }
+
+    #[test]
+    fn blit_clipped() {
+        let mut src = Region::new(4, 2);
+        src.str(0, 0, "abcd");
+        src.str(0, 1, "efgh");
+
+        /*
+         * The source rectangle is clipped to the source, and the result to
+         * the destination.
+         */
+        let mut dst = Region::new(5, 2);
+        dst.blit(&src, Rect::new(1, 1, 10, 10), 3, 0);
+        assert_eq!(row(&dst, 0), "   fg");
+        assert_eq!(row(&dst, 1), "     ");
+
+        /*
+         * Offsets beyond either region copy nothing.
+         */
+        let mut dst = Region::new(5, 2);
+        dst.blit(&src, Rect::new(4, 0, 2, 2), 0, 0);
+        dst.blit(&src, Rect::new(0, 0, 2, 2), 5, 0);
+        dst.blit(&src, Rect::new(0, 0, 2, 2), 0, 2);
+        assert_eq!(row(&dst, 0), "     ");
+        assert_eq!(row(&dst, 1), "     ");
+    }
+
+    #[test]
+    fn blit_cuts_wide_characters() {
+        let mut src = Region::new(6, 1);
+        src.str(0, 0, "中ab中");
+
+        /*
+         * Halves of wide characters at either edge of the source rectangle
+         * become blanks, but keep their format.
+         */
+        src.cell_mut(1, 0).unwrap().bold();
+        let mut dst = Region::new(4, 1);
+        dst.blit(&src, Rect::new(1, 0, 4, 1), 0, 0);
+        assert_eq!(row(&dst, 0), " ab ");
+        assert_eq!(widths(&dst, 0), [1, 1, 1, 1]);
+        assert!(dst.cell(0, 0).unwrap().format().bold);
+
+        /*
+         * The same applies where the destination edge cuts the copy short.
+         */
+        let mut dst = Region::new(5, 1);
+        dst.blit(&src, Rect::new(0, 0, 6, 1), 0, 0);
+        assert_eq!(row(&dst, 0), "中ab ");
+
+        /*
+         * Wide characters in the destination that are partly overwritten
+         * are blanked entirely.
+         */
+        let mut dst = Region::new(6, 1);
+        dst.str(0, 0, "中..中");
+        dst.blit(&src, Rect::new(2, 0, 1, 1), 1, 0);
+        dst.blit(&src, Rect::new(3, 0, 1, 1), 4, 0);
+        assert_eq!(row(&dst, 0), " a..b ");
+    }
+
+    #[test]
+    fn composite_transparency() {
+        let red = Colour::C16(1);
+        let mut dst = Region::new(4, 1);
+        dst.strf(
+            0,
+            0,
+            "中xy",
+            &Format { fg: red, bg: red, ..Default::default() },
+        );
+
+        let mut src = Region::new_transparent(4, 1);
+        let keep = Format {
+            fg: Colour::UseExisting,
+            bg: Colour::UseExisting,
+            ..Default::default()
+        };
+        src.chrf(1, 0, 'z', &keep);
+        src.cell_mut(3, 0).unwrap().bold();
+        assert!(src.cell(0, 0).unwrap().is_transparent());
+        assert!(src.cell(3, 0).unwrap().is_transparent());
+        assert!(!src.cell(1, 0).unwrap().is_transparent());
+
+        /*
+         * A transparent cell over half of a wide character leaves it whole.
+         */
+        let mut over = Region::new(3, 1);
+        over.str(0, 0, "中x");
+        over.composite(&src, Rect::new(0, 0, 1, 1), 1, 0);
+        assert_eq!(row(&over, 0), "中x");
+        assert_eq!(widths(&over, 0), [2, 0, 1]);
+
+        /*
+         * Text written with UseExisting colours keeps those underneath.
+         */
+        dst.composite(&src, Rect::new(0, 0, 4, 1), 0, 0);
+        assert_eq!(row(&dst, 0), " zxy");
+        let z = dst.cell(1, 0).unwrap().format();
+        assert_eq!((z.fg, z.bg), (red, red));
+
+        /*
+         * Transparency depends only on the contents and the background, so
+         * other attributes of a blank cell are not carried over.
+         */
+        let y = dst.cell(3, 0).unwrap();
+        assert_eq!(y.contents(), "y");
+        assert!(!y.format().bold);
+
+        /*
+         * A plain blit copies transparent cells, which are drawn with the
+         * default colours.
+         */
+        let mut dst = Region::new(2, 1);
+        dst.str(0, 0, "ab");
+        dst.blit(&src, Rect::new(0, 0, 1, 1), 0, 0);
+        assert_eq!(row(&dst, 0), " b");
+    }
+
+    #[test]
+    fn fill_clear_and_paint_rect() {
+        let bold = Format { bold: true, ..Default::default() };
+        let mut r = Region::new(5, 3);
+        r.fill_rect(Rect::new(1, 1, 10, 10), '#', &bold);
+        assert_eq!(row(&r, 0), "     ");
+        assert_eq!(row(&r, 1), " ####");
+        assert!(r.cell(4, 2).unwrap().format().bold);
+
+        r.clear_rect(Rect::new(2, 1, 2, 1));
+        assert_eq!(row(&r, 1), " #  #");
+        assert!(!r.cell(2, 1).unwrap().format().bold);
+
+        /*
+         * Painting leaves the contents alone.
+         */
+        let red = Format { bg: Colour::C16(1), ..Default::default() };
+        r.paint_rect(Rect::new(0, 2, 2, 1), &red);
+        assert_eq!(row(&r, 2), " ####");
+        assert_eq!(r.cell(1, 2).unwrap().format().bg, Colour::C16(1));
+        assert_eq!(r.cell(2, 2).unwrap().format().bg, Colour::Default);
+
+        /*
+         * A wide fill character is confined to the rectangle.
+         */
+        let mut r = Region::new(5, 1);
+        r.fill_rect(Rect::new(0, 0, 5, 1), '中', &bold);
+        assert_eq!(row(&r, 0), "中中 ");
+    }
+
+    #[test]
+    fn rect_operations_break_wide_characters() {
+        let red = Format { bg: Colour::C16(1), ..Default::default() };
+
+        /*
+         * Half of a wide character is never restyled or cleared on its own.
+         */
+        let mut g = Region::new(4, 1);
+        g.str(0, 0, "中中");
+        g.paint_rect(Rect::new(1, 0, 2, 1), &red);
+        assert_eq!(widths(&g, 0), [1, 1, 1, 1]);
+        assert_eq!(g.cell(0, 0).unwrap().format().bg, Colour::Default);
+        assert_eq!(g.cell(1, 0).unwrap().format().bg, Colour::C16(1));
+
+        let mut g = Region::new(6, 1);
+        g.str(0, 0, "中中中");
+        g.paint_rect(Rect::new(1, 0, 4, 1), &red);
+        assert_eq!(row(&g, 0), "  中  ");
+        assert_eq!(g.cell(3, 0).unwrap().format().bg, Colour::C16(1));
+
+        g.str(0, 0, "中中中");
+        g.clear_rect(Rect::new(3, 0, 2, 1));
+        assert_eq!(row(&g, 0), "中    ");
+    }
 }
@@ -0,0 +1,87 @@
+use std::io::{Error, Result};
+use std::mem::MaybeUninit;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+/*
+ * Incremented by the signal handler each time a SIGWINCH arrives.  Each
+ * watcher remembers the last value it saw, so that any number of them can
+ * share the one handler.
+ */
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+static INSTALL: Once = Once::new();
+
+extern "C" fn handle_winch(_: libc::c_int) {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/**
+ * Determine the size of the terminal attached to a file descriptor, as
+ * (width, height).
+ */
+pub fn terminal_size(fd: RawFd) -> Result<(usize, usize)> {
+    let mut winsize: MaybeUninit<libc::winsize> = MaybeUninit::uninit();
+    let r = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, winsize.as_mut_ptr()) };
+    if r != 0 {
+        Err(Error::last_os_error())
+    } else {
+        let winsize = unsafe { winsize.assume_init() };
+        Ok((winsize.ws_col as usize, winsize.ws_row as usize))
+    }
+}
+
+/**
+ * Watch for changes to the size of a terminal.  The first watcher created in
+ * a process installs a SIGWINCH handler, replacing any existing handler for
+ * that signal.  The application is expected to call changed() periodically;
+ * e.g., once per frame.
+ */
+pub struct ResizeWatcher {
+    fd: RawFd,
+    seen: usize,
+}
+
+impl ResizeWatcher {
+    pub fn new(fd: RawFd) -> Result<ResizeWatcher> {
+        let mut res = Ok(());
+
+        INSTALL.call_once(|| {
+            let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+            sa.sa_sigaction = handle_winch as *const () as libc::sighandler_t;
+            sa.sa_flags = libc::SA_RESTART;
+            unsafe { libc::sigemptyset(&mut sa.sa_mask) };
+
+            if unsafe {
+                libc::sigaction(libc::SIGWINCH, &sa, std::ptr::null_mut())
+            } != 0
+            {
+                res = Err(Error::last_os_error());
+            }
+        });
+        res?;
+
+        Ok(ResizeWatcher { fd, seen: GENERATION.load(Ordering::SeqCst) })
+    }
+
+    /**
+     * The current size of the terminal, as (width, height).
+     */
+    pub fn size(&self) -> Result<(usize, usize)> {
+        terminal_size(self.fd)
+    }
+
+    /**
+     * If the terminal has been resized since the last call, return the new
+     * size as (width, height).
+     */
+    pub fn changed(&mut self) -> Option<Result<(usize, usize)>> {
+        let gen = GENERATION.load(Ordering::SeqCst);
+        if gen == self.seen {
+            return None;
+        }
+
+        self.seen = gen;
+        Some(self.size())
+    }
+}
@@ -0,0 +1,567 @@
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/*
+ * Magic numbers for the legacy format, in which numeric capabilities are 16
+ * bits wide, and the newer ncurses format in which they are 32 bits wide.
+ */
+const MAGIC_LEGACY: i16 = 0o432;
+const MAGIC_32BIT: i16 = 0o1036;
+
+/*
+ * Indexes of the standard capabilities we care about, in the order defined by
+ * term(5).
+ */
+pub(crate) const BOOL_AUTO_RIGHT_MARGIN: usize = 1;
+pub(crate) const BOOL_EAT_NEWLINE_GLITCH: usize = 4;
+pub(crate) const BOOL_BACK_COLOR_ERASE: usize = 28;
+pub(crate) const NUM_MAX_COLORS: usize = 13;
+pub(crate) const STR_CHANGE_SCROLL_REGION: usize = 3;
+pub(crate) const STR_ENTER_CA_MODE: usize = 28;
+pub(crate) const STR_ERASE_CHARS: usize = 37;
+pub(crate) const STR_EXIT_CA_MODE: usize = 40;
+pub(crate) const STR_PARM_DELETE_LINE: usize = 106;
+pub(crate) const STR_PARM_INDEX: usize = 109;
+pub(crate) const STR_PARM_INSERT_LINE: usize = 110;
+pub(crate) const STR_PARM_RINDEX: usize = 113;
+pub(crate) const STR_REPEAT_CHAR: usize = 121;
+pub(crate) const STR_ROW_ADDRESS: usize = 127;
+pub(crate) const STR_ENTER_ITALICS_MODE: usize = 311;
+
+/**
+ * A compiled terminfo entry, as produced by tic(1).
+ */
+pub(crate) struct Terminfo {
+    bools: Vec<bool>,
+    numbers: Vec<Option<i32>>,
+    strings: Vec<Option<Vec<u8>>>,
+    extended: HashSet<String>,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid terminfo: {}", msg))
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() - self.pos < n {
+            return Err(invalid("truncated entry"));
+        }
+        let out = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        let b = self.bytes(2)?;
+        Ok(i16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        usize::try_from(self.i16()?).map_err(|_| invalid("negative count"))
+    }
+
+    fn align(&mut self) {
+        if self.pos & 1 == 1 && self.pos < self.buf.len() {
+            self.pos += 1;
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+}
+
+/**
+ * Fetch the NUL-terminated string at the given offset within a string table.
+ */
+fn string_at(table: &[u8], offset: i16) -> Result<Option<&[u8]>> {
+    if offset < 0 {
+        /*
+         * The capability is either absent or has been cancelled.
+         */
+        return Ok(None);
+    }
+
+    let table =
+        table.get(offset as usize..).ok_or_else(|| invalid("offset"))?;
+    let len = table
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("unterminated string"))?;
+    Ok(Some(&table[..len]))
+}
+
+impl Terminfo {
+    pub(crate) fn parse(buf: &[u8]) -> Result<Terminfo> {
+        let mut r = Reader { buf, pos: 0 };
+
+        let wide = match r.i16()? {
+            MAGIC_LEGACY => false,
+            MAGIC_32BIT => true,
+            _ => return Err(invalid("bad magic number")),
+        };
+        let number = |r: &mut Reader| -> Result<Option<i32>> {
+            let n = if wide { r.i32()? } else { r.i16()? as i32 };
+            Ok(if n < 0 { None } else { Some(n) })
+        };
+
+        let names_size = r.count()?;
+        let bool_count = r.count()?;
+        let num_count = r.count()?;
+        let str_count = r.count()?;
+        let strtab_size = r.count()?;
+
+        r.bytes(names_size)?;
+        let bools = r.bytes(bool_count)?.iter().map(|&b| b == 1).collect();
+        r.align();
+        let numbers =
+            (0..num_count).map(|_| number(&mut r)).collect::<Result<_>>()?;
+        let offsets =
+            (0..str_count).map(|_| r.i16()).collect::<Result<Vec<_>>>()?;
+        let table = r.bytes(strtab_size)?;
+        let strings = offsets
+            .iter()
+            .map(|&o| Ok(string_at(table, o)?.map(|s| s.to_vec())))
+            .collect::<Result<_>>()?;
+
+        let mut ti =
+            Terminfo { bools, numbers, strings, extended: HashSet::new() };
+
+        /*
+         * An ncurses extended capabilities section may follow the standard
+         * capabilities.  If present, the names of the extended capabilities
+         * are stored in the string table after all of the string values.
+         */
+        r.align();
+        if r.remaining() == 0 {
+            return Ok(ti);
+        }
+
+        let ext_bools = r.count()?;
+        let ext_nums = r.count()?;
+        let ext_strs = r.count()?;
+        let _ext_offsets = r.count()?;
+        let ext_strtab_size = r.count()?;
+
+        let bools = r.bytes(ext_bools)?.to_vec();
+        r.align();
+        let nums = (0..ext_nums)
+            .map(|_| number(&mut r))
+            .collect::<Result<Vec<_>>>()?;
+        let offsets =
+            (0..ext_strs).map(|_| r.i16()).collect::<Result<Vec<_>>>()?;
+        let name_offsets = (0..(ext_bools + ext_nums + ext_strs))
+            .map(|_| r.i16())
+            .collect::<Result<Vec<_>>>()?;
+        let table = r.bytes(ext_strtab_size)?;
+
+        let mut present = Vec::with_capacity(name_offsets.len());
+        present.extend(bools.iter().map(|&b| b == 1));
+        present.extend(nums.iter().map(|n| n.is_some()));
+        let mut names_start = 0;
+        for &o in offsets.iter() {
+            let s = string_at(table, o)?;
+            if let Some(s) = s {
+                names_start = names_start.max(o as usize + s.len() + 1);
+            }
+            present.push(s.is_some());
+        }
+        let names = &table[names_start.min(table.len())..];
+
+        for (&o, present) in name_offsets.iter().zip(present) {
+            let name = string_at(names, o)?.ok_or_else(|| invalid("name"))?;
+            if present {
+                ti.extended.insert(String::from_utf8_lossy(name).to_string());
+            }
+        }
+
+        Ok(ti)
+    }
+
+    /**
+     * Locate and parse the compiled entry for a terminal type, searching the
+     * directories in the same order as the system curses library.
+     */
+    pub(crate) fn load(term: &str) -> Result<Terminfo> {
+        if term.is_empty() || term.contains('/') || term.starts_with('.') {
+            return Err(Error::new(ErrorKind::InvalidInput, "bad terminal"));
+        }
+
+        let first = term.chars().next().unwrap();
+        let subdirs = [first.to_string(), format!("{:x}", first as u32)];
+
+        for dir in search_path() {
+            for sub in subdirs.iter() {
+                let path = dir.join(sub).join(term);
+                if let Ok(buf) = std::fs::read(&path) {
+                    return Terminfo::parse(&buf);
+                }
+            }
+        }
+
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no terminfo entry for {:?}", term),
+        ))
+    }
+
+    pub(crate) fn bool(&self, idx: usize) -> bool {
+        self.bools.get(idx).copied().unwrap_or(false)
+    }
+
+    pub(crate) fn number(&self, idx: usize) -> Option<i32> {
+        self.numbers.get(idx).copied().flatten()
+    }
+
+    pub(crate) fn string(&self, idx: usize) -> Option<&[u8]> {
+        self.strings.get(idx).and_then(|s| s.as_deref())
+    }
+
+    /**
+     * Is the named user-defined capability present in the entry?
+     */
+    pub(crate) fn has_extended(&self, name: &str) -> bool {
+        self.extended.contains(name)
+    }
+}
+
+fn search_path() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+
+    let defaults = [
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+        "/usr/share/lib/terminfo",
+        "/usr/gnu/share/terminfo",
+    ];
+
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        /*
+         * An empty element in this list stands for the default locations.
+         */
+        for dir in list.split(':') {
+            if dir.is_empty() {
+                dirs.extend(defaults.iter().map(PathBuf::from));
+            } else {
+                dirs.push(PathBuf::from(dir));
+            }
+        }
+    } else {
+        dirs.extend(defaults.iter().map(PathBuf::from));
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /*
+     * Just enough of tic(1) to build small entries for the parser.
+     */
+    #[derive(Default)]
+    pub(crate) struct Entry {
+        pub(crate) wide: bool,
+        pub(crate) bools: Vec<bool>,
+        pub(crate) numbers: Vec<i32>,
+        pub(crate) strings: Vec<Option<&'static [u8]>>,
+        pub(crate) ext_bools: Vec<(&'static str, bool)>,
+        pub(crate) ext_numbers: Vec<(&'static str, i32)>,
+        pub(crate) ext_strings: Vec<(&'static str, Option<&'static [u8]>)>,
+    }
+
+    fn push_i16(out: &mut Vec<u8>, n: i32) {
+        out.extend_from_slice(&(n as i16).to_le_bytes());
+    }
+
+    fn push_number(out: &mut Vec<u8>, wide: bool, n: i32) {
+        if wide {
+            out.extend_from_slice(&n.to_le_bytes());
+        } else {
+            push_i16(out, n);
+        }
+    }
+
+    fn pad(out: &mut Vec<u8>) {
+        if out.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+
+    /**
+     * Append the values to a string table, returning their offsets.
+     */
+    fn table(table: &mut Vec<u8>, values: &[Option<&[u8]>]) -> Vec<i32> {
+        let start = table.len();
+        let mut offsets = Vec::new();
+        for v in values {
+            match v {
+                Some(s) => {
+                    offsets.push((table.len() - start) as i32);
+                    table.extend_from_slice(s);
+                    table.push(0);
+                }
+                None => offsets.push(-1),
+            }
+        }
+        offsets
+    }
+
+    impl Entry {
+        /**
+         * Encode the standard section, which ends where the extended section
+         * would start.
+         */
+        fn standard(&self) -> Vec<u8> {
+            let names = b"test|small test entry\0";
+            let mut strtab = Vec::new();
+            let offsets = table(&mut strtab, &self.strings);
+
+            let mut out = Vec::new();
+            push_i16(&mut out, if self.wide { 0o1036 } else { 0o432 });
+            push_i16(&mut out, names.len() as i32);
+            push_i16(&mut out, self.bools.len() as i32);
+            push_i16(&mut out, self.numbers.len() as i32);
+            push_i16(&mut out, self.strings.len() as i32);
+            push_i16(&mut out, strtab.len() as i32);
+            out.extend_from_slice(names);
+            out.extend(self.bools.iter().map(|&b| b as u8));
+            pad(&mut out);
+            for &n in self.numbers.iter() {
+                push_number(&mut out, self.wide, n);
+            }
+            for &o in offsets.iter() {
+                push_i16(&mut out, o);
+            }
+            out.extend_from_slice(&strtab);
+            out
+        }
+
+        pub(crate) fn encode(&self) -> Vec<u8> {
+            let mut out = self.standard();
+            if self.ext_bools.is_empty()
+                && self.ext_numbers.is_empty()
+                && self.ext_strings.is_empty()
+            {
+                return out;
+            }
+            pad(&mut out);
+
+            let values: Vec<_> = self.ext_strings.iter().map(|s| s.1).collect();
+            let names: Vec<_> = (self.ext_bools.iter().map(|b| b.0))
+                .chain(self.ext_numbers.iter().map(|n| n.0))
+                .chain(self.ext_strings.iter().map(|s| s.0))
+                .map(|n| Some(n.as_bytes()))
+                .collect();
+            let mut strtab = Vec::new();
+            let offsets = table(&mut strtab, &values);
+            let name_offsets = table(&mut strtab, &names);
+
+            push_i16(&mut out, self.ext_bools.len() as i32);
+            push_i16(&mut out, self.ext_numbers.len() as i32);
+            push_i16(&mut out, values.len() as i32);
+            push_i16(&mut out, (values.len() + names.len()) as i32);
+            push_i16(&mut out, strtab.len() as i32);
+            out.extend(self.ext_bools.iter().map(|b| b.1 as u8));
+            pad(&mut out);
+            for n in self.ext_numbers.iter() {
+                push_number(&mut out, self.wide, n.1);
+            }
+            for &o in offsets.iter().chain(name_offsets.iter()) {
+                push_i16(&mut out, o);
+            }
+            out.extend_from_slice(&strtab);
+            out
+        }
+    }
+
+    fn sample(wide: bool) -> Entry {
+        let mut bools = vec![false; 29];
+        bools[BOOL_AUTO_RIGHT_MARGIN] = true;
+        bools[BOOL_BACK_COLOR_ERASE] = true;
+
+        let mut numbers = vec![-1; 14];
+        numbers[0] = 80;
+        numbers[2] = 24;
+        numbers[NUM_MAX_COLORS] = if wide { 0x1000000 } else { 256 };
+
+        let mut strings = vec![None; 41];
+        strings[STR_CHANGE_SCROLL_REGION] = Some(&b"\x1b[%i%p1%d;%p2%dr"[..]);
+        strings[STR_ENTER_CA_MODE] = Some(&b"\x1b[?1049h"[..]);
+        strings[STR_EXIT_CA_MODE] = Some(&b""[..]);
+
+        Entry { wide, bools, numbers, strings, ..Entry::default() }
+    }
+
+    fn sample_extended() -> Entry {
+        Entry {
+            ext_bools: vec![("AX", true), ("XT", false)],
+            ext_numbers: vec![("U8", 1), ("RGB", -1)],
+            ext_strings: vec![
+                ("Smulx", Some(&b"\x1b[4:%p1%dm"[..])),
+                ("Ms", None),
+                ("Sync", Some(&b"\x1bP=%p1%ds\x1b\\"[..])),
+            ],
+            ..sample(true)
+        }
+    }
+
+    fn check_standard(ti: &Terminfo, max_colors: i32) {
+        assert!(ti.bool(BOOL_AUTO_RIGHT_MARGIN));
+        assert!(ti.bool(BOOL_BACK_COLOR_ERASE));
+        assert!(!ti.bool(BOOL_EAT_NEWLINE_GLITCH));
+        assert!(!ti.bool(1000));
+
+        assert_eq!(ti.number(0), Some(80));
+        assert_eq!(ti.number(1), None);
+        assert_eq!(ti.number(2), Some(24));
+        assert_eq!(ti.number(NUM_MAX_COLORS), Some(max_colors));
+        assert_eq!(ti.number(1000), None);
+
+        assert_eq!(
+            ti.string(STR_CHANGE_SCROLL_REGION),
+            Some(&b"\x1b[%i%p1%d;%p2%dr"[..])
+        );
+        assert_eq!(ti.string(STR_ENTER_CA_MODE), Some(&b"\x1b[?1049h"[..]));
+        assert_eq!(ti.string(STR_EXIT_CA_MODE), Some(&b""[..]));
+        assert_eq!(ti.string(STR_ERASE_CHARS), None);
+        assert_eq!(ti.string(STR_ENTER_ITALICS_MODE), None);
+    }
+
+    #[test]
+    fn legacy_format() {
+        let ti = Terminfo::parse(&sample(false).encode()).unwrap();
+        check_standard(&ti, 256);
+        assert!(!ti.has_extended("Sync"));
+    }
+
+    #[test]
+    fn wide_numbers() {
+        let ti = Terminfo::parse(&sample(true).encode()).unwrap();
+        check_standard(&ti, 0x1000000);
+    }
+
+    #[test]
+    fn padding_after_bools() {
+        /*
+         * The sample has an odd number of bytes before the numbers, which are
+         * then padded to an even offset.  With one more boolean there is no
+         * padding.
+         */
+        for extra in [false, true] {
+            let mut entry = sample(false);
+            if extra {
+                entry.bools.push(false);
+            }
+            let ti = Terminfo::parse(&entry.encode()).unwrap();
+            check_standard(&ti, 256);
+        }
+    }
+
+    #[test]
+    fn extended_capabilities() {
+        let ti = Terminfo::parse(&sample_extended().encode()).unwrap();
+        check_standard(&ti, 0x1000000);
+
+        assert!(ti.has_extended("AX"));
+        assert!(ti.has_extended("U8"));
+        assert!(ti.has_extended("Smulx"));
+        assert!(ti.has_extended("Sync"));
+        assert!(!ti.has_extended("XT"));
+        assert!(!ti.has_extended("RGB"));
+        assert!(!ti.has_extended("Ms"));
+        assert!(!ti.has_extended("Tc"));
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut buf = sample(false).encode();
+        buf[0] = 0x1F;
+        buf[1] = 0x8B;
+        assert!(Terminfo::parse(&buf).is_err());
+        assert!(Terminfo::parse(b"").is_err());
+        assert!(Terminfo::parse(b"\x1a").is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let entry = sample_extended();
+        let standard = entry.standard().len();
+        let buf = entry.encode();
+
+        /*
+         * Cutting the entry off just before the extended section leaves a
+         * valid entry, but anywhere else it must be rejected.
+         */
+        for n in 0..buf.len() {
+            let ti = Terminfo::parse(&buf[..n]);
+            if n == standard || n == standard + standard % 2 {
+                assert!(!ti.unwrap().has_extended("Sync"));
+            } else {
+                assert!(ti.is_err(), "accepted {} bytes", n);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let header = |counts: [i16; 5]| {
+            let mut buf = 0o432i16.to_le_bytes().to_vec();
+            for c in counts {
+                buf.extend_from_slice(&c.to_le_bytes());
+            }
+            buf
+        };
+
+        /*
+         * Negative section sizes.
+         */
+        assert!(Terminfo::parse(&header([-1, 0, 0, 0, 0])).is_err());
+        assert!(Terminfo::parse(&header([0, 0, 0, 0, -2])).is_err());
+
+        /*
+         * A string offset past the end of the table.
+         */
+        let mut buf = header([0, 0, 0, 1, 2]);
+        buf.extend_from_slice(&[9, 0, b'a', 0]);
+        assert!(Terminfo::parse(&buf).is_err());
+
+        /*
+         * A string without a terminating NUL.
+         */
+        let mut buf = header([0, 0, 0, 1, 2]);
+        buf.extend_from_slice(&[0, 0, b'a', b'b']);
+        assert!(Terminfo::parse(&buf).is_err());
+
+        /*
+         * An extended capability whose name is missing.
+         */
+        let mut buf = header([0, 0, 0, 0, 0]);
+        buf.extend_from_slice(&[1, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        buf.extend_from_slice(&[1, 0, 0xFF, 0xFF]);
+        assert!(Terminfo::parse(&buf).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use super::layout::{str_width, TextLayout};
+use super::region::*;
+
+/**
+ * A run of text in a single format.
+ */
+#[derive(Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub format: Format,
+}
+
+/**
+ * A sequence of spans to be drawn one after another on the same line (unless
+ * it is wrapped).
+ */
+#[derive(Clone, PartialEq, Default)]
+pub struct Line {
+    pub spans: Vec<Span>,
+}
+
+/**
+ * A sequence of lines.
+ */
+#[derive(Clone, PartialEq, Default)]
+pub struct Text {
+    pub lines: Vec<Line>,
+}
+
+impl Span {
+    pub fn new(text: &str, format: &Format) -> Span {
+        Span { text: text.to_string(), format: *format }
+    }
+
+    /**
+     * The number of columns needed to display the span.
+     */
+    pub fn width(&self) -> usize {
+        str_width(&self.text)
+    }
+}
+
+impl From<&str> for Span {
+    fn from(text: &str) -> Self {
+        Span::new(text, &Format::default())
+    }
+}
+
+impl From<String> for Span {
+    fn from(text: String) -> Self {
+        Span { text, format: Format::default() }
+    }
+}
+
+impl Line {
+    pub fn new() -> Line {
+        Line::default()
+    }
+
+    /**
+     * Append text in the given format, returning the line so that calls may
+     * be chained; e.g., Line::new().span("12:00", &grey).str(" hello").
+     */
+    pub fn span(mut self, text: &str, format: &Format) -> Line {
+        self.push(Span::new(text, format));
+        self
+    }
+
+    /**
+     * Append text in the default format.
+     */
+    pub fn str(self, text: &str) -> Line {
+        self.span(text, &Format::default())
+    }
+
+    pub fn push(&mut self, span: Span) {
+        self.spans.push(span);
+    }
+
+    /**
+     * The number of columns needed to display the line without wrapping.
+     */
+    pub fn width(&self) -> usize {
+        self.spans.iter().map(Span::width).sum()
+    }
+
+    /**
+     * The text of the line without any formatting.
+     */
+    pub fn plain(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+}
+
+impl From<Span> for Line {
+    fn from(span: Span) -> Self {
+        Line { spans: vec![span] }
+    }
+}
+
+impl From<Vec<Span>> for Line {
+    fn from(spans: Vec<Span>) -> Self {
+        Line { spans }
+    }
+}
+
+impl From<&str> for Line {
+    fn from(text: &str) -> Self {
+        Line::from(Span::from(text))
+    }
+}
+
+impl Text {
+    pub fn new() -> Text {
+        Text::default()
+    }
+
+    /**
+     * Append a line, returning the text so that calls may be chained.
+     */
+    pub fn line<L: Into<Line>>(mut self, line: L) -> Text {
+        self.push(line.into());
+        self
+    }
+
+    pub fn push(&mut self, line: Line) {
+        self.lines.push(line);
+    }
+
+    /**
+     * The number of columns needed to display the widest line without
+     * wrapping.
+     */
+    pub fn width(&self) -> usize {
+        self.lines.iter().map(Line::width).max().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.lines.len()
+    }
+
+    /**
+     * Determine the number of lines needed to lay out the text in the given
+     * width, were there no limit on the height.
+     */
+    pub fn measure(&self, width: usize, layout: &TextLayout) -> usize {
+        layout.measure(&self.flatten().0, width)
+    }
+
+    /**
+     * Join the lines together with newlines, noting the byte range covered by
+     * each span.
+     */
+    fn flatten(&self) -> (String, Vec<(usize, usize, Format)>) {
+        let mut plain = String::new();
+        let mut runs = Vec::new();
+
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                plain.push('\n');
+            }
+            for span in line.spans.iter() {
+                let start = plain.len();
+                plain.push_str(&span.text);
+                runs.push((start, plain.len(), span.format));
+            }
+        }
+
+        (plain, runs)
+    }
+}
+
+impl From<Line> for Text {
+    fn from(line: Line) -> Self {
+        Text { lines: vec![line] }
+    }
+}
+
+impl From<Vec<Line>> for Text {
+    fn from(lines: Vec<Line>) -> Self {
+        Text { lines }
+    }
+}
+
+impl From<&str> for Text {
+    fn from(text: &str) -> Self {
+        Text { lines: text.lines().map(Line::from).collect() }
+    }
+}
+
+impl Region {
+    /**
+     * Draw each span of a line in turn, starting at (x, y).  Returns the
+     * number of columns written.
+     */
+    pub fn spans(&mut self, x: usize, y: usize, line: &Line) -> usize {
+        let right = self.width();
+        let mut w = 0;
+        for span in line.spans.iter() {
+            let f = Some(&span.format);
+            w += self.text(0, right, x + w, y, &span.text, f);
+        }
+        w
+    }
+
+    /**
+     * Draw rich text within a rectangle, wrapping, aligning, and truncating it
+     * as directed by the layout.  The ellipsis, if needed, takes the format of
+     * the text it follows.  Returns the number of lines used.
+     */
+    pub fn rich_layout(
+        &mut self,
+        r: Rect,
+        text: &Text,
+        layout: &TextLayout,
+    ) -> usize {
+        let r = r.clip(self.width(), self.height());
+        let (left, right) = (r.x, r.x + r.width);
+        let (plain, runs) = text.flatten();
+
+        let placed = layout.place(&plain, r.width, r.height);
+        for p in placed.iter() {
+            let (mut x, y) = (r.x + p.x, r.y + p.y);
+            let mut last = runs
+                .iter()
+                .find(|(s, e, _)| *s <= p.start && p.start < *e)
+                .map(|(_, _, f)| *f)
+                .unwrap_or_default();
+
+            for (s, e, f) in runs.iter() {
+                let (s, e) = ((*s).max(p.start), (*e).min(p.end));
+                if s < e {
+                    x += self.text(left, right, x, y, &plain[s..e], Some(f));
+                    last = *f;
+                }
+            }
+
+            if p.ellipsis {
+                self.text(left, right, x, y, &layout.ellipsis, Some(&last));
+            }
+        }
+
+        placed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Wrap;
+
+    fn row(r: &Region, y: usize) -> String {
+        (0..r.width())
+            .map(|x| r.cell(x, y).unwrap())
+            .filter(|c| !c.is_continuation())
+            .map(|c| c.contents())
+            .collect()
+    }
+
+    fn bold() -> Format {
+        Format { bold: true, ..Default::default() }
+    }
+
+    fn italic() -> Format {
+        Format { italic: true, ..Default::default() }
+    }
+
+    /*
+     * For each column of a row, "b" for bold, "i" for italic, or "." for
+     * neither.
+     */
+    fn styles(r: &Region, y: usize) -> String {
+        (0..r.width())
+            .map(|x| {
+                let f = r.cell(x, y).unwrap().format();
+                match (f.bold, f.italic) {
+                    (true, _) => 'b',
+                    (_, true) => 'i',
+                    _ => '.',
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn building_lines() {
+        let line =
+            Line::new().span("ab", &bold()).str("中").span("", &italic());
+        assert_eq!(line.spans.len(), 3);
+        assert_eq!(line.width(), 4);
+        assert_eq!(line.plain(), "ab中");
+        assert!(line.spans[0].format == bold());
+        assert!(line.spans[1].format == Format::default());
+
+        assert!(Line::from("x") == Line::from(Span::from("x".to_string())));
+
+        let text = Text::from("one\ntwo three\n").line(line);
+        assert_eq!(text.height(), 3);
+        assert_eq!(text.width(), 9);
+        assert_eq!(text.lines[2].plain(), "ab中");
+        assert_eq!(Text::new().width(), 0);
+    }
+
+    #[test]
+    fn spans_keep_their_formats() {
+        let line =
+            Line::new().span("ab", &bold()).str("c").span("de", &italic());
+        let mut r = Region::new(7, 1);
+        assert_eq!(r.spans(1, 0, &line), 5);
+        assert_eq!(row(&r, 0), " abcde ");
+        assert_eq!(styles(&r, 0), ".bb.ii.");
+
+        /*
+         * Spans are clipped at the edge of the region, or of a view.
+         */
+        let mut r = Region::new(4, 1);
+        assert_eq!(r.spans(1, 0, &line), 3);
+        assert_eq!(styles(&r, 0), ".bb.");
+
+        let mut r = Region::new(6, 1);
+        let mut v = r.view_mut(1, 0, 4, 1);
+        assert_eq!(v.spans(2, 0, &line), 2);
+        assert_eq!(row(&r, 0), "   ab ");
+        assert_eq!(styles(&r, 0), "...bb.");
+    }
+
+    #[test]
+    fn rich_layout_wraps_across_spans() {
+        let text = Text::from(
+            Line::new()
+                .span("aa bb", &bold())
+                .str(" cc")
+                .span("dd ee", &italic()),
+        );
+        let layout = TextLayout { align: Align::Right, ..Default::default() };
+
+        let mut r = Region::new(6, 3);
+        assert_eq!(text.measure(5, &layout), 3);
+        assert_eq!(r.rich_layout(Rect::new(1, 0, 5, 3), &text, &layout), 3);
+        assert_eq!(row(&r, 0), " aa bb");
+        assert_eq!(styles(&r, 0), ".bbbbb");
+        assert_eq!(row(&r, 1), "  ccdd");
+        assert_eq!(styles(&r, 1), "....ii");
+        assert_eq!(row(&r, 2), "    ee");
+        assert_eq!(styles(&r, 2), "....ii");
+    }
+
+    #[test]
+    fn rich_layout_ellipsis_format() {
+        /*
+         * The ellipsis follows the format of the text before it, even where
+         * that is the last span to fit.
+         */
+        let text = Text::new()
+            .line(Line::new().span("abc", &bold()).span("def", &italic()))
+            .line("more");
+        let layout = TextLayout { wrap: Wrap::None, ..Default::default() };
+
+        let mut r = Region::new(5, 1);
+        assert_eq!(r.rich_layout(Rect::new(0, 0, 5, 1), &text, &layout), 1);
+        assert_eq!(row(&r, 0), "abcd…");
+        assert_eq!(styles(&r, 0), "bbbii");
+
+        let mut r = Region::new(4, 2);
+        let text = Text::from(Line::new().span("ab", &bold()).str("cdefgh"));
+        r.rich_layout(Rect::new(0, 0, 3, 1), &text, &layout);
+        assert_eq!(row(&r, 0), "ab… ");
+        assert_eq!(styles(&r, 0), "bbb.");
+    }
+}
@@ -0,0 +1,297 @@
+use super::layout::TextLayout;
+use super::region::*;
+use super::text::{Line, Text};
+
+/**
+ * A rectangular part of a Region, borrowed for drawing.  Coordinates are
+ * relative to the top-left corner of the view, and nothing written through the
+ * view will land outside of it.
+ */
+pub struct View<'a> {
+    region: &'a mut Region,
+    rect: Rect,
+}
+
+impl<'a> View<'a> {
+    pub(crate) fn new(region: &'a mut Region, rect: Rect) -> View<'a> {
+        View { region, rect }
+    }
+
+    /**
+     * The area of the underlying region that this view covers.
+     */
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn height(&self) -> usize {
+        self.rect.height
+    }
+
+    pub fn width(&self) -> usize {
+        self.rect.width
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x >= self.rect.width || y >= self.rect.height {
+            None
+        } else {
+            self.region.cell(self.rect.x + x, self.rect.y + y)
+        }
+    }
+
+    pub fn cell_mut(&mut self, x: usize, y: usize) -> Option<&mut Cell> {
+        if x >= self.rect.width || y >= self.rect.height {
+            None
+        } else {
+            self.region.cell_mut(self.rect.x + x, self.rect.y + y)
+        }
+    }
+
+    pub fn chr(&mut self, x: usize, y: usize, ch: char) -> usize {
+        self.put(x, y, ch.encode_utf8(&mut [0u8; 4]), None)
+    }
+
+    pub fn chrf(&mut self, x: usize, y: usize, ch: char, f: &Format) -> usize {
+        self.put(x, y, ch.encode_utf8(&mut [0u8; 4]), Some(f))
+    }
+
+    fn put(
+        &mut self,
+        x: usize,
+        y: usize,
+        g: &str,
+        f: Option<&Format>,
+    ) -> usize {
+        if y >= self.rect.height {
+            return 0;
+        }
+
+        let Rect { x: left, width, .. } = self.rect;
+        self.region.put(left, left + width, left + x, self.rect.y + y, g, f)
+    }
+
+    pub fn str(&mut self, x: usize, y: usize, s: &str) -> usize {
+        self.text(x, y, s, None)
+    }
+
+    pub fn strf(&mut self, x: usize, y: usize, s: &str, f: &Format) -> usize {
+        self.text(x, y, s, Some(f))
+    }
+
+    pub fn spans(&mut self, x: usize, y: usize, line: &Line) -> usize {
+        let mut w = 0;
+        for span in line.spans.iter() {
+            w += self.text(x + w, y, &span.text, Some(&span.format));
+        }
+        w
+    }
+
+    fn text(
+        &mut self,
+        x: usize,
+        y: usize,
+        s: &str,
+        f: Option<&Format>,
+    ) -> usize {
+        if y >= self.rect.height {
+            return 0;
+        }
+
+        let Rect { x: left, width, .. } = self.rect;
+        self.region.text(left, left + width, left + x, self.rect.y + y, s, f)
+    }
+
+    /**
+     * Translate a rectangle within this view into the coordinates of the
+     * underlying region, clipping it to the bounds of the view.
+     */
+    fn outer(&self, r: Rect) -> Rect {
+        let r = r.clip(self.rect.width, self.rect.height);
+        Rect::new(self.rect.x + r.x, self.rect.y + r.y, r.width, r.height)
+    }
+
+    pub fn clear(&mut self) {
+        self.region.clear_rect(self.rect);
+    }
+
+    pub fn fill_rect(&mut self, r: Rect, ch: char, f: &Format) {
+        self.region.fill_rect(self.outer(r), ch, f);
+    }
+
+    pub fn clear_rect(&mut self, r: Rect) {
+        self.region.clear_rect(self.outer(r));
+    }
+
+    pub fn paint_rect(&mut self, r: Rect, f: &Format) {
+        self.region.paint_rect(self.outer(r), f);
+    }
+
+    /**
+     * Draw text within a rectangle of this view; see Region::text_layout().
+     */
+    pub fn text_layout(
+        &mut self,
+        r: Rect,
+        s: &str,
+        f: &Format,
+        layout: &TextLayout,
+    ) -> usize {
+        self.region.text_layout(self.outer(r), s, f, layout)
+    }
+
+    /**
+     * Draw rich text within a rectangle of this view; see
+     * Region::rich_layout().
+     */
+    pub fn rich_layout(
+        &mut self,
+        r: Rect,
+        text: &Text,
+        layout: &TextLayout,
+    ) -> usize {
+        self.region.rich_layout(self.outer(r), text, layout)
+    }
+
+    /**
+     * Place the cursor of the underlying region at a position within this
+     * view.  A position outside the view removes the cursor.
+     */
+    pub fn set_cursor(&mut self, curs: Option<Point>) {
+        let curs =
+            curs.filter(|p| p.x < self.rect.width && p.y < self.rect.height);
+        self.region.set_cursor(
+            curs.map(|p| Point::new(self.rect.x + p.x, self.rect.y + p.y)),
+        );
+    }
+
+    /**
+     * Borrow a rectangular part of this view as a further nested view.  The
+     * coordinates are relative to this view, and the new view is clipped to
+     * its bounds.
+     */
+    pub fn view_mut(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> View<'_> {
+        let rect = self.outer(Rect::new(x, y, width, height));
+        View::new(self.region, rect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(r: &Region, y: usize) -> String {
+        let mut s = String::new();
+        for x in 0..r.width() {
+            let c = r.cell(x, y).unwrap();
+            if !c.is_continuation() {
+                s.push_str(c.contents());
+            }
+        }
+        s
+    }
+
+    #[test]
+    fn writes_are_clipped() {
+        let mut r = Region::new(8, 3);
+        let mut v = r.view_mut(2, 1, 4, 1);
+        assert_eq!(v.str(1, 0, "abcdef"), 3);
+        assert_eq!(v.chr(4, 0, 'x'), 0);
+        assert_eq!(v.str(0, 1, "below"), 0);
+        assert!(v.cell(4, 0).is_none());
+        assert!(v.cell_mut(0, 1).is_none());
+
+        assert_eq!(row(&r, 0), "        ");
+        assert_eq!(row(&r, 1), "   abc  ");
+        assert_eq!(row(&r, 2), "        ");
+    }
+
+    #[test]
+    fn rect_is_clipped_to_region() {
+        let mut r = Region::new(6, 2);
+        assert_eq!(r.view_mut(4, 1, 10, 10).rect(), Rect::new(4, 1, 2, 1));
+
+        /*
+         * A view that starts beyond the region is empty, and drawing through
+         * it does nothing.
+         */
+        let mut v = r.view_mut(9, 5, 3, 3);
+        assert_eq!((v.width(), v.height()), (0, 0));
+        assert_eq!(v.str(0, 0, "abc"), 0);
+        v.clear();
+        v.set_cursor(Some(Point::new(0, 0)));
+        assert!(r.cursor().is_none());
+        assert_eq!(row(&r, 0), "      ");
+    }
+
+    #[test]
+    fn nested_views() {
+        let mut r = Region::new(10, 4);
+        let mut outer = r.view_mut(2, 1, 6, 3);
+        let mut inner = outer.view_mut(3, 1, 10, 10);
+        assert_eq!(inner.rect(), Rect::new(5, 2, 3, 2));
+        assert_eq!(inner.str(0, 0, "nested"), 3);
+        inner.set_cursor(Some(Point::new(1, 1)));
+
+        assert_eq!(row(&r, 2), "     nes  ");
+        assert!(r.cursor() == Some(Point::new(6, 3)));
+
+        let mut outer = r.view_mut(2, 1, 6, 3);
+        outer.set_cursor(Some(Point::new(6, 0)));
+        assert!(r.cursor().is_none());
+    }
+
+    #[test]
+    fn wide_character_at_right_edge() {
+        /*
+         * A wide character that does not fit in the view is replaced with a
+         * blank, and the cell beyond the view is untouched.
+         */
+        let mut r = Region::new(6, 1);
+        r.str(0, 0, "......");
+        let mut v = r.view_mut(1, 0, 3, 1);
+        assert_eq!(v.str(0, 0, "ab中"), 3);
+        assert_eq!(row(&r, 0), ".ab ..");
+        assert_eq!(r.cell(4, 0).unwrap().contents(), ".");
+    }
+
+    #[test]
+    fn wide_character_straddling_edges() {
+        let mut r = Region::new(6, 1);
+        r.str(0, 0, "中ab中");
+
+        /*
+         * Writing into the trailing half of a wide character at the left
+         * edge, or the leading half at the right edge, cannot leave half of
+         * it behind outside the view.
+         */
+        let mut v = r.view_mut(1, 0, 4, 1);
+        v.str(0, 0, "x");
+        v.str(3, 0, "y");
+        assert_eq!(row(&r, 0), " xaby ");
+
+        r.str(0, 0, "中ab中");
+        r.view_mut(1, 0, 4, 1).clear();
+        assert_eq!(row(&r, 0), "      ");
+    }
+
+    #[test]
+    fn combining_mark_at_left_edge() {
+        /*
+         * A mark at the first column of the view has nothing in the view to
+         * attach to, so it must not reach the cell outside.
+         */
+        let mut r = Region::new(4, 1);
+        r.str(0, 0, "ab");
+        let mut v = r.view_mut(1, 0, 3, 1);
+        assert_eq!(v.str(0, 0, "\u{301}c"), 1);
+        assert_eq!(r.cell(0, 0).unwrap().contents(), "a");
+        assert_eq!(row(&r, 0), "ac  ");
+    }
+}